@@ -0,0 +1,25 @@
+//! Renders the wavy rectangles to a PNG without opening a window.
+//!
+//! ```bash
+//! cargo run --example export_wavy_rects -- wavy.png 512 512 1.5
+//! ```
+//!
+//! The arguments are the output path, width, height and time in seconds.
+
+use try_egui_eframe::widgets::wavy_rects::{export, ui::WavyRectangles};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    pretty_env_logger::init();
+
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or_else(|| "wavy_rects.png".to_owned());
+    let width = args.next().map(|s| s.parse()).transpose()?.unwrap_or(512);
+    let height = args.next().map(|s| s.parse()).transpose()?.unwrap_or(width);
+    let time_seconds = args.next().map(|s| s.parse()).transpose()?.unwrap_or(0.0);
+
+    let mut rects = WavyRectangles::default();
+    rects.time_seconds = time_seconds;
+    export::save_png(&rects, width, height, &path)?;
+    println!("wrote {path}");
+    Ok(())
+}
@@ -1,48 +1,90 @@
 use egui::Button;
 use log::info;
 
-use crate::widgets::{simple_image::ui::SimpleImage, wavy_rects::ui::WavyRectanglesWithControls};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::hot_reload::ShaderWatcher;
+use crate::widgets::{
+    gpu, simple_image::ui::SimpleImage, wavy_rects, wavy_rects::ui::WavyRectanglesWithControls,
+};
 
-#[derive(serde::Deserialize, serde::Serialize, Debug, Default)]
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 #[serde(default)]
 pub struct MainApp {
-    wavy_rectangles: WavyRectanglesWithControls,
-    wavy_rectangles2: WavyRectanglesWithControls,
+    wavy_rectangles: Vec<WavyRectanglesWithControls>,
     simple_image: SimpleImage,
     should_display_profiler: bool,
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip)]
+    shader_watcher: Option<ShaderWatcher>,
+}
+
+impl Default for MainApp {
+    fn default() -> Self {
+        Self {
+            wavy_rectangles: vec![Default::default(), Default::default()],
+            simple_image: Default::default(),
+            should_display_profiler: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            shader_watcher: None,
+        }
+    }
 }
 
 impl MainApp {
     /// Called once before the first frame.
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
         egui_extras::install_image_loaders(&cc.egui_ctx);
+        gpu::install(&cc.egui_ctx, cc.wgpu_render_state.as_ref());
         puffin::set_scopes_on(true);
         // This is also where you can customize the look and feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
 
         // Load previous app state (if any).
         // Note that you must enable the `persistence` feature for this to work.
-        let mut app: Self = if let Some(storage) = cc.storage {
+        if let Some(storage) = cc.storage {
             eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default()
         } else {
             Default::default()
+        }
+    }
+
+    /// Reload the widgets' shaders when they change on disk.
+    ///
+    /// Requires running from the source tree.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_shader_hot_reload(mut self) -> Self {
+        self.shader_watcher = Some(ShaderWatcher::new());
+        self
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn reload_shaders(&mut self, ctx: &egui::Context, frame: &eframe::Frame) {
+        let Some(watcher) = &mut self.shader_watcher else {
+            return;
         };
-        app.wavy_rectangles.setup_renderer(cc);
-        app.wavy_rectangles2.setup_renderer(cc);
-        app.simple_image.setup_renderer(cc);
-        app
+        let changes = watcher.poll(ctx);
+        if let (Some(source), Some(rc)) = (changes.wavy_rects, frame.wgpu_render_state()) {
+            wavy_rects::ui::reload_shader(rc, &source);
+        }
+        if let Some(source) = changes.simple_image {
+            self.simple_image.set_shader_source(source);
+        }
     }
 }
 
 impl eframe::App for MainApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         puffin::GlobalProfiler::lock().new_frame();
 
+        #[cfg(not(target_arch = "wasm32"))]
+        self.reload_shaders(ctx, frame);
+
         let Self {
             wavy_rectangles,
-            wavy_rectangles2,
             simple_image,
             should_display_profiler,
+            ..
         } = self;
 
         if *should_display_profiler {
@@ -55,34 +97,38 @@ impl eframe::App for MainApp {
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
                     if ui.button("Quit").clicked() {
-                        _frame.close();
+                        frame.close();
                     }
                 });
             });
         });
 
         egui::SidePanel::left("side_panel").show(ctx, |ui| {
+            if ui.button("Add rectangles").clicked() {
+                wavy_rectangles.push(Default::default());
+            }
             ui.with_layout(egui::Layout::bottom_up(egui::Align::Min), |ui| {
                 egui::warn_if_debug_build(ui);
                 ui.hyperlink_to(
                     "try-egui-eframe",
                     "https://github.com/nclack/try-egui-eframe",
                 );
-                if !*should_display_profiler {
-                    if ui.add(Button::new("Open Profiler")).clicked() {
-                        *should_display_profiler = true;
-                    }
+                if !*should_display_profiler && ui.add(Button::new("Open Profiler")).clicked() {
+                    *should_display_profiler = true;
                 }
             });
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("Rounded rectangles");
-            ui.columns(2, |columns| {
-                columns[0].add(wavy_rectangles);
-                columns[1].add(wavy_rectangles2);
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                ui.heading("Rounded rectangles");
+                let removed = wavy_rects::ui::show_panels(ui, wavy_rectangles);
+                if let (Some(widget), Some(rc)) = (removed, frame.wgpu_render_state()) {
+                    widget.release(&mut rc.renderer.write().callback_resources);
+                }
+                ui.add(&mut *simple_image);
+                simple_image.save_if_requested(frame.wgpu_render_state());
             });
-            ui.add(simple_image);
         });
     }
 
@@ -0,0 +1,116 @@
+//! Reloads the widgets' shaders when their files change on disk, so they can
+//! be worked on without rebuilding the app.
+//!
+//! Native only, and opt-in: set `SHADER_HOT_RELOAD=1` in the environment or
+//! in `.env`.
+
+use std::{fs, path::PathBuf, time::Duration, time::SystemTime};
+
+/// The environment variable that turns hot reloading on.
+pub const ENV_VAR: &str = "SHADER_HOT_RELOAD";
+
+// How often the files are checked.
+const POLL_INTERVAL_SECONDS: f64 = 0.5;
+
+/// A shader file in the source tree along with the version of it that was
+/// built into the app.
+macro_rules! watched {
+    ($path:literal) => {
+        WatchedFile::new(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/src/", $path),
+            include_str!($path),
+        )
+    };
+}
+
+#[derive(Debug)]
+struct WatchedFile {
+    path: PathBuf,
+    built_in: &'static str,
+    // `None` until the file has been seen.
+    modified: Option<SystemTime>,
+}
+
+impl WatchedFile {
+    fn new(path: &str, built_in: &'static str) -> Self {
+        Self {
+            path: path.into(),
+            built_in,
+            modified: None,
+        }
+    }
+
+    /// The file's contents if it changed since the last call.
+    ///
+    /// The first time, the contents are only returned if they differ from
+    /// the built in version. Otherwise in-app edits that were restored on
+    /// startup would be thrown away.
+    fn poll(&mut self) -> Option<String> {
+        let modified = match fs::metadata(&self.path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                if self.modified.take().is_some() {
+                    log::warn!("can't watch {}: {e}", self.path.display());
+                }
+                return None;
+            }
+        };
+        let first = self.modified.is_none();
+        if self.modified.replace(modified) == Some(modified) {
+            return None;
+        }
+        match fs::read_to_string(&self.path) {
+            Ok(source) if first && source == self.built_in => None,
+            Ok(source) => {
+                log::info!("{} changed", self.path.display());
+                Some(source)
+            }
+            Err(e) => {
+                log::warn!("can't read {}: {e}", self.path.display());
+                None
+            }
+        }
+    }
+}
+
+/// Shader sources that changed on disk.
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub wavy_rects: Option<String>,
+    pub simple_image: Option<String>,
+}
+
+/// Polls the shader files for changes.
+#[derive(Debug)]
+pub struct ShaderWatcher {
+    wavy_rects: WatchedFile,
+    simple_image: WatchedFile,
+    next_poll: f64,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> Self {
+        log::info!("watching shaders for changes");
+        Self {
+            wavy_rects: watched!("widgets/wavy_rects/painter.wgsl"),
+            simple_image: watched!("widgets/simple_image/compute.wgsl"),
+            next_poll: 0.0,
+        }
+    }
+
+    /// Checks the files every so often, and keeps the app repainting so
+    /// that changes are noticed while it's otherwise idle.
+    pub fn poll(&mut self, ctx: &egui::Context) -> Changes {
+        let now = ctx.input(|i| i.time);
+        if now < self.next_poll {
+            return Changes::default();
+        }
+        puffin::profile_function!();
+        self.next_poll = now + POLL_INTERVAL_SECONDS;
+        ctx.request_repaint_after(Duration::from_secs_f64(POLL_INTERVAL_SECONDS));
+        Changes {
+            wavy_rects: self.wavy_rects.poll(),
+            simple_image: self.simple_image.poll(),
+        }
+    }
+}
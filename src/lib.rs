@@ -1,6 +1,10 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
-mod widgets;
+#[cfg(not(target_arch = "wasm32"))]
+mod hot_reload;
+pub mod widgets;
 
 pub use app::MainApp;
+#[cfg(not(target_arch = "wasm32"))]
+pub use hot_reload::ENV_VAR as SHADER_HOT_RELOAD;
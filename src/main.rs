@@ -4,22 +4,73 @@
 // When compiling natively:
 #[cfg(not(target_arch = "wasm32"))]
 fn main() -> eframe::Result<()> {
-    use log::info;
+    use std::sync::Arc;
+
+    use eframe::{egui_wgpu, wgpu};
+    use log::{info, warn};
 
     dotenv::dotenv().ok();
     pretty_env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
     info!("Logging enabled");
 
+    // Opt in by setting e.g. `SHADER_HOT_RELOAD=1` in `.env`.
+    let shader_hot_reload =
+        std::env::var_os(try_egui_eframe::SHADER_HOT_RELOAD).is_some_and(|v| v != "0");
+
+    // Ask for timestamp queries when they're available so GPU work can be
+    // timed in the profiler.
+    let wgpu_options = egui_wgpu::WgpuConfiguration::default();
+    let device_descriptor = wgpu_options.device_descriptor.clone();
+    let wgpu_options = egui_wgpu::WgpuConfiguration {
+        device_descriptor: Arc::new(move |adapter| {
+            let mut descriptor = device_descriptor(adapter);
+            descriptor.features |= adapter.features() & wgpu::Features::TIMESTAMP_QUERY;
+            descriptor
+        }),
+        ..wgpu_options
+    };
+
+    // Set `RENDERER=glow` to see the widgets' CPU fallbacks.
+    let renderer = match std::env::var("RENDERER").as_deref() {
+        Ok("glow") => eframe::Renderer::Glow,
+        _ => eframe::Renderer::Wgpu,
+    };
+
     let native_options = eframe::NativeOptions {
         vsync: false,
         fullscreen: true,
+        renderer,
+        wgpu_options,
         ..Default::default()
     };
-    eframe::run_native(
+    let app_creator = move |cc: &eframe::CreationContext<'_>| -> Box<dyn eframe::App> {
+        let app = try_egui_eframe::MainApp::new(cc);
+        Box::new(if shader_hot_reload {
+            app.with_shader_hot_reload()
+        } else {
+            app
+        })
+    };
+    let result = eframe::run_native(
         "try_egui_eframe",
-        native_options,
-        Box::new(|cc| Box::new(try_egui_eframe::MainApp::new(cc))),
-    )
+        native_options.clone(),
+        Box::new(app_creator),
+    );
+    match result {
+        // e.g. no adapter, or the driver is too old
+        Err(e) if renderer == eframe::Renderer::Wgpu => {
+            warn!("wgpu failed to start ({e}), falling back to glow");
+            eframe::run_native(
+                "try_egui_eframe",
+                eframe::NativeOptions {
+                    renderer: eframe::Renderer::Glow,
+                    ..native_options
+                },
+                Box::new(app_creator),
+            )
+        }
+        result => result,
+    }
 }
 
 // When compiling to web using trunk:
@@ -0,0 +1,57 @@
+//! Errors from rendering widgets to images without a window.
+
+use std::fmt;
+
+use eframe::wgpu;
+
+/// Why an export failed.
+#[derive(Debug)]
+pub enum ExportError {
+    /// No wgpu adapter could be found.
+    NoDevice,
+    /// The image is empty or larger than the device supports.
+    InvalidSize { width: u32, height: u32, max: u32 },
+    /// Reading the rendered texture back failed.
+    Readback(wgpu::BufferAsyncError),
+    /// Encoding or writing the PNG failed.
+    Image(image::ImageError),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoDevice => write!(f, "no wgpu adapter available"),
+            Self::InvalidSize { width, height, max } => write!(
+                f,
+                "can't export a {width}x{height} image: both sides must be 1 to {max} pixels"
+            ),
+            Self::Readback(e) => write!(f, "reading back the image failed: {e}"),
+            Self::Image(e) => write!(f, "writing the image failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::NoDevice | Self::InvalidSize { .. } => None,
+            Self::Readback(e) => Some(e),
+            Self::Image(e) => Some(e),
+        }
+    }
+}
+
+/// Checks that `device` can render a `width` x `height` texture, which would
+/// otherwise be a device error that panics.
+pub(crate) fn check_size(
+    device: &wgpu::Device,
+    width: u32,
+    height: u32,
+) -> Result<(), ExportError> {
+    let max = device.limits().max_texture_dimension_2d;
+    if (1..=max).contains(&width) && (1..=max).contains(&height) {
+        Ok(())
+    } else {
+        Err(ExportError::InvalidSize { width, height, max })
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Once,
+};
+
+use eframe::{
+    egui_wgpu::{CallbackResources, RenderState},
+    wgpu::TextureFormat,
+};
+use egui::{vec2, Align2, Context, FontId, Id, Painter, Rect};
+
+/// The format of the render target egui paints into.
+///
+/// Render pipelines need this, but egui-wgpu doesn't pass it to
+/// `egui_wgpu::CallbackTrait::prepare`, so [`install`] keeps it in the
+/// callback resources for widgets that set up their GPU resources on demand.
+#[derive(Clone, Copy, Debug)]
+pub struct TargetFormat(pub TextureFormat);
+
+/// Call once, before the first frame, with `cc.wgpu_render_state`.
+///
+/// Pass `None` when eframe isn't rendering with wgpu (e.g. with the glow
+/// backend). The widgets then draw a CPU fallback.
+pub fn install(ctx: &Context, render_state: Option<&RenderState>) {
+    ctx.data_mut(|d| d.insert_temp(available_id(), render_state.is_some()));
+    if let Some(rc) = render_state {
+        rc.renderer
+            .write()
+            .callback_resources
+            .insert(TargetFormat(rc.target_format));
+    }
+}
+
+/// True unless [`install`] was told the widgets can't render with wgpu.
+pub fn is_available(ctx: &Context) -> bool {
+    ctx.data(|d| d.get_temp(available_id())).unwrap_or(true)
+}
+
+/// The render target's format for a paint callback.
+///
+/// `None` if [`install`] wasn't called. That's logged once, and the widgets
+/// skip drawing rather than panic.
+pub fn target_format(resources: &CallbackResources) -> Option<TextureFormat> {
+    static MISSING: Once = Once::new();
+    let format = resources.get::<TargetFormat>().map(|&TargetFormat(f)| f);
+    if format.is_none() {
+        MISSING.call_once(|| {
+            log::error!("widgets::gpu::install() wasn't called, so the widgets can't draw")
+        });
+    }
+    format
+}
+
+fn available_id() -> Id {
+    Id::new("widgets::gpu::available")
+}
+
+/// Marks a widget drawn with a CPU fallback.
+pub fn paint_unavailable_notice(painter: &Painter, rect: Rect) {
+    painter.text(
+        rect.left_top() + vec2(4.0, 4.0),
+        Align2::LEFT_TOP,
+        "GPU unavailable",
+        FontId::proportional(12.0),
+        painter.ctx().style().visuals.warn_fg_color,
+    );
+}
+
+/// A device for rendering without a window.
+///
+/// Prefers a software adapter (llvmpipe, lavapipe, WARP) so the results are
+/// the same on machines without a GPU, and falls back to any adapter.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn headless_device() -> Option<(eframe::wgpu::Device, eframe::wgpu::Queue)> {
+    use eframe::wgpu;
+
+    let instance = wgpu::Instance::default();
+    let adapter = [true, false]
+        .into_iter()
+        .find_map(|force_fallback_adapter| {
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                force_fallback_adapter,
+                ..Default::default()
+            }))
+        })?;
+    log::info!("headless adapter: {:?}", adapter.get_info());
+    let descriptor = wgpu::DeviceDescriptor {
+        label: Some("headless device"),
+        features: wgpu::Features::empty(),
+        limits: adapter.limits(),
+    };
+    match pollster::block_on(adapter.request_device(&descriptor, None)) {
+        Ok(device) => Some(device),
+        Err(e) => {
+            log::warn!("can't create a headless device: {e}");
+            None
+        }
+    }
+}
+
+/// The [headless device](headless_device) for tests that need a GPU.
+///
+/// Panics if there's no adapter, so a machine without one can't pass the
+/// tests without checking anything, unless `SKIP_GPU_TESTS` is set. Then it
+/// returns `None` and the test should return early.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn test_device() -> Option<(eframe::wgpu::Device, eframe::wgpu::Queue)> {
+    let device = headless_device();
+    if device.is_none() {
+        assert!(
+            std::env::var_os("SKIP_GPU_TESTS").is_some(),
+            "no wgpu adapter available; run with SKIP_GPU_TESTS=1 to skip the GPU tests"
+        );
+        eprintln!("skipping: no wgpu adapter available");
+    }
+    device
+}
+
+/// A new id for keying a widget's state in the callback resources.
+///
+/// Ids are never reused so that a released widget's state can't be mistaken
+/// for another's.
+pub fn next_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
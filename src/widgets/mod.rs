@@ -1,3 +1,8 @@
+#[cfg(not(target_arch = "wasm32"))]
+pub mod export;
+pub mod gpu;
 pub mod player;
+pub mod png;
+pub mod readback;
 pub mod simple_image;
 pub mod wavy_rects;
@@ -1,84 +1,991 @@
+use std::{hash::Hash, time::Duration};
+
 use egui::{
-    include_image, vec2, Align, ImageButton, Layout, NumExt, ProgressBar, Response, Vec2, Widget,
+    emath::remap_clamp, include_image, vec2, Align, CursorIcon, DragValue, Id, ImageButton, Layout,
+    NumExt, ProgressBar, Response, Sense, Vec2, Widget,
 };
 use serde::{Deserialize, Serialize};
 
-#[derive(Default, Serialize, Deserialize, Debug)]
+/// What playback does at the ends of the loop range.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoopMode {
+    /// Stop at the end.
+    Once,
+    /// Jump back to the start.
+    #[default]
+    Loop,
+    /// Turn around and play the other way.
+    PingPong,
+}
+
+impl LoopMode {
+    pub const ALL: [Self; 3] = [Self::Once, Self::Loop, Self::PingPong];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Once => "once",
+            Self::Loop => "loop",
+            Self::PingPong => "ping-pong",
+        }
+    }
+}
+
+/// Where a player's time comes from.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Clock {
+    /// Real time, from egui's input time.
+    #[default]
+    WallClock,
+    /// Moves on by a fixed step every frame, however long the frame took,
+    /// so playback shows the same frames on every run.
+    FixedStep { step_seconds: f64 },
+}
+
+impl Clock {
+    /// 60 frames per second.
+    pub const DEFAULT_STEP_SECONDS: f64 = 1.0 / 60.0;
+
+    /// Frame rates a fixed step can have.
+    pub const FPS_RANGE: std::ops::RangeInclusive<f64> = 1.0..=240.0;
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::WallClock => "real time",
+            Self::FixedStep { .. } => "fixed step",
+        }
+    }
+
+    /// How far the clock moves in a frame that took `input_delta` seconds of
+    /// egui's input time.
+    pub fn advance(&self, input_delta: f64) -> f64 {
+        match self {
+            Self::WallClock => input_delta,
+            Self::FixedStep { step_seconds } => *step_seconds,
+        }
+    }
+
+    /// The same clock with its step limited to [`Self::FPS_RANGE`].
+    fn clamped(self) -> Self {
+        match self {
+            Self::WallClock => self,
+            Self::FixedStep { step_seconds } => {
+                let (min_fps, max_fps) = Self::FPS_RANGE.into_inner();
+                Self::FixedStep {
+                    step_seconds: step_seconds.clamp(1.0 / max_fps, 1.0 / min_fps),
+                }
+            }
+        }
+    }
+
+    /// The length of a frame, for stepping through them.
+    pub fn frame_seconds(&self) -> f64 {
+        match self {
+            Self::WallClock => Self::DEFAULT_STEP_SECONDS,
+            Self::FixedStep { step_seconds } => *step_seconds,
+        }
+    }
+}
+
+/// Plays back a clip of `duration_seconds`, or a range of it, at a
+/// variable speed.
+///
+/// Times passed in are on the player's clock, as returned by
+/// [`PlayerState::tick`].
+///
+/// The clock only lasts for a session, so the player is saved as a position
+/// instead, and playback picks up from there when loaded.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(from = "SavedPlayer", into = "SavedPlayer")]
 pub struct PlayerState {
     is_playing: bool,
 
-    last_pause_time: Option<f64>,
+    clock: Clock,
+    // Time on `clock`. Only `tick` moves it, so it's continuous when the
+    // clock changes.
+    clock_time: f64,
+    // egui's input time at the last tick
+    last_input_time: Option<f64>,
+
+    // Position at `anchor_time`, before the loop mode is applied. Playback
+    // moves it by `speed` seconds per second. Rebased whenever anything that
+    // affects playback changes.
+    anchor_position: f64,
+    anchor_time: f64,
+    // negative plays in reverse
+    speed: f64,
+
+    loop_mode: LoopMode,
+    // Playback stays within this part of the clip. `None` is all of it.
+    loop_range: Option<[f64; 2]>,
+    duration_seconds: f64,
+
+    // Repaints are requested at most this often while playing.
+    max_fps: f64,
+}
+
+impl Default for PlayerState {
+    fn default() -> Self {
+        Self {
+            is_playing: false,
+            clock: Clock::default(),
+            clock_time: 0.0,
+            last_input_time: None,
+            anchor_position: 0.0,
+            anchor_time: 0.0,
+            speed: 1.0,
+            loop_mode: LoopMode::default(),
+            loop_range: None,
+            duration_seconds: 10.0,
+            max_fps: 60.0,
+        }
+    }
+}
+
+/// How [`PlayerState`] is persisted: everything but the session's clock.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct SavedPlayer {
+    is_playing: bool,
+    /// Position when saved, in seconds.
+    position: f64,
+    speed: f64,
+    clock: Clock,
+    loop_mode: LoopMode,
+    loop_range: Option<[f64; 2]>,
+    duration_seconds: f64,
+    max_fps: f64,
+}
+
+impl Default for SavedPlayer {
+    fn default() -> Self {
+        PlayerState::default().into()
+    }
+}
+
+impl From<PlayerState> for SavedPlayer {
+    fn from(player: PlayerState) -> Self {
+        Self {
+            is_playing: player.is_playing,
+            position: player.progress(player.clock_time),
+            speed: player.speed,
+            clock: player.clock,
+            loop_mode: player.loop_mode,
+            loop_range: player.loop_range,
+            duration_seconds: player.duration_seconds,
+            max_fps: player.max_fps,
+        }
+    }
+}
+
+impl From<SavedPlayer> for PlayerState {
+    fn from(saved: SavedPlayer) -> Self {
+        let mut player = Self {
+            is_playing: saved.is_playing,
+            loop_mode: saved.loop_mode,
+            loop_range: saved.loop_range,
+            ..Default::default()
+        };
+        // Go through the setters, so a hand edited file can't break anything.
+        let now = player.now();
+        player.set_clock(saved.clock);
+        player.set_speed(now, saved.speed);
+        player.set_duration_seconds(now, saved.duration_seconds);
+        player.set_max_fps(saved.max_fps);
+        player.seek(now, saved.position);
+        player
+    }
 }
 
 impl PlayerState {
-    // Progress in seconds adjusted for the last pause time
-    pub fn progress(&self, current_time: f64) -> f64 {
-        let Self {
-            is_playing,
-            last_pause_time,
-        } = self;
-        if *is_playing {
-            current_time - (last_pause_time.unwrap_or(0.0))
+    // Shortest clip or loop range the player accepts, so positions stay well
+    // defined.
+    const MIN_DURATION_SECONDS: f64 = 0.1;
+
+    /// Slowest and fastest playback, either way.
+    pub const SPEED_RANGE: std::ops::RangeInclusive<f64> = 0.1..=10.0;
+
+    /// Frame rate caps the player accepts.
+    pub const MAX_FPS_RANGE: std::ops::RangeInclusive<f64> = 1.0..=240.0;
+
+    /// Moves the player's clock on to the frame at `input_time`, which is
+    /// `egui::InputState::time`, and returns the clock's time. Call once a
+    /// frame; further calls in the same frame don't move the clock.
+    pub fn tick(&mut self, input_time: f64) -> f64 {
+        if let Some(last) = self.last_input_time {
+            let delta = input_time - last;
+            if delta > 0.0 {
+                self.clock_time += self.clock.advance(delta);
+            }
+        }
+        self.last_input_time = Some(input_time);
+        self.clock_time
+    }
+
+    /// The clock's time at the last [`tick`](Self::tick).
+    pub fn now(&self) -> f64 {
+        self.clock_time
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.clock
+    }
+
+    /// Switches clocks. Takes effect from the next tick. A fixed step is
+    /// clamped to [`Clock::FPS_RANGE`].
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock.clamped();
+    }
+
+    /// Pauses and moves `frames` frames of the clock on, or back for negative
+    /// `frames`, scaled by the speed as in playback.
+    pub fn step(&mut self, current_time: f64, frames: i32) {
+        self.set_playing(current_time, false);
+        let delta = frames as f64 * self.clock.frame_seconds() * self.speed.abs();
+        self.seek(current_time, self.progress(current_time) + delta);
+    }
+
+    // Position at `current_time`, before the loop mode is applied.
+    fn unwrapped_position(&self, current_time: f64) -> f64 {
+        if self.is_playing {
+            self.anchor_position + self.speed * (current_time - self.anchor_time)
         } else {
-            last_pause_time.unwrap_or(0.0)
+            self.anchor_position
+        }
+    }
+
+    /// Position in seconds at `current_time`, within the loop range.
+    pub fn progress(&self, current_time: f64) -> f64 {
+        let p = self.unwrapped_position(current_time);
+        let [start, end] = self.loop_range();
+        let len = end - start;
+        match self.loop_mode {
+            LoopMode::Once => p.clamp(start, end),
+            LoopMode::Loop => {
+                // Show the end of a lap as the end rather than the start, so
+                // seeking to the end lands there.
+                let u = (p - start).rem_euclid(len);
+                if u == 0.0 && p > start {
+                    end
+                } else {
+                    start + u
+                }
+            }
+            LoopMode::PingPong => {
+                let u = (p - start).rem_euclid(2.0 * len);
+                start + if u <= len { u } else { 2.0 * len - u }
+            }
         }
     }
+
+    // Anchors playback at `current_time` without moving it. Ping-pong keeps
+    // its direction since the unwrapped position is kept.
+    fn rebase(&mut self, current_time: f64) {
+        self.anchor_position = match self.loop_mode {
+            LoopMode::Once => self.progress(current_time),
+            LoopMode::Loop | LoopMode::PingPong => self.unwrapped_position(current_time),
+        };
+        self.anchor_time = current_time;
+    }
+
+    /// Whether playback in [`LoopMode::Once`] has reached the end it's
+    /// heading for.
+    pub fn is_finished(&self, current_time: f64) -> bool {
+        let [start, end] = self.loop_range();
+        let p = self.unwrapped_position(current_time);
+        self.loop_mode == LoopMode::Once
+            && if self.speed > 0.0 {
+                p >= end
+            } else {
+                p <= start
+            }
+    }
+
+    /// Pauses playback that has finished. Call once a frame.
+    pub fn update(&mut self, current_time: f64) {
+        if self.is_playing && self.is_finished(current_time) {
+            self.set_playing(current_time, false);
+        }
+    }
+
+    /// Moves playback to `position` seconds, clamped to the loop range,
+    /// without changing whether it's playing.
+    pub fn seek(&mut self, current_time: f64, position: f64) {
+        let [start, end] = self.loop_range();
+        self.anchor_position = position.clamp(start, end);
+        self.anchor_time = current_time;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.is_playing
+    }
+
+    /// Plays or pauses, keeping the position at `current_time`. Playing
+    /// after finishing starts over.
+    pub fn set_playing(&mut self, current_time: f64, is_playing: bool) {
+        self.rebase(current_time);
+        if is_playing && self.is_finished(current_time) {
+            let [start, end] = self.loop_range();
+            self.anchor_position = if self.speed > 0.0 { start } else { end };
+        }
+        self.is_playing = is_playing;
+    }
+
+    /// Playback speed; negative when playing in reverse.
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Sets the playback speed, keeping the position at `current_time`. The
+    /// magnitude is clamped to [`Self::SPEED_RANGE`].
+    pub fn set_speed(&mut self, current_time: f64, speed: f64) {
+        let (min, max) = Self::SPEED_RANGE.into_inner();
+        self.rebase(current_time);
+        self.speed = speed.abs().clamp(min, max).copysign(speed);
+    }
+
+    pub fn loop_mode(&self) -> LoopMode {
+        self.loop_mode
+    }
+
+    pub fn set_loop_mode(&mut self, current_time: f64, loop_mode: LoopMode) {
+        let position = self.progress(current_time);
+        self.loop_mode = loop_mode;
+        self.seek(current_time, position);
+    }
+
+    /// The part of the clip playback stays in, as `[start, end]` seconds.
+    pub fn loop_range(&self) -> [f64; 2] {
+        let d = self.duration_seconds;
+        match self.loop_range {
+            Some([start, end]) if end.min(d) - start >= Self::MIN_DURATION_SECONDS => {
+                [start, end.min(d)]
+            }
+            _ => [0.0, d],
+        }
+    }
+
+    /// Restricts playback to `start..end` seconds, or to the whole clip for
+    /// `None`. Ranges shorter than 0.1 s are ignored.
+    pub fn set_loop_range(&mut self, current_time: f64, range: Option<[f64; 2]>) {
+        let position = self.progress(current_time);
+        self.loop_range = range.map(|[start, end]| [start.min(end).max(0.0), start.max(end)]);
+        self.seek(current_time, position);
+    }
+
+    pub fn duration_seconds(&self) -> f64 {
+        self.duration_seconds
+    }
+
+    /// Changes the clip length, keeping the position at `current_time` if it
+    /// still fits. Positions past the new end wrap around, as in playback.
+    pub fn set_duration_seconds(&mut self, current_time: f64, duration_seconds: f64) {
+        let position = self.progress(current_time);
+        self.duration_seconds = duration_seconds.at_least(Self::MIN_DURATION_SECONDS);
+        self.seek(current_time, position.rem_euclid(self.duration_seconds));
+    }
+
+    /// The most frames per second playback asks egui for.
+    pub fn max_fps(&self) -> f64 {
+        self.max_fps
+    }
+
+    /// Sets the frame rate cap, clamped to [`Self::MAX_FPS_RANGE`].
+    pub fn set_max_fps(&mut self, max_fps: f64) {
+        let (min, max) = Self::MAX_FPS_RANGE.into_inner();
+        self.max_fps = max_fps.clamp(min, max);
+    }
+
+    /// How long until the next frame should be painted, or `None` when
+    /// nothing is moving and the app can go idle.
+    pub fn repaint_after(&self) -> Option<Duration> {
+        self.is_playing
+            .then(|| Duration::from_secs_f64(1.0 / self.max_fps))
+    }
 }
 
 pub struct Controller<'a, 'b> {
+    id_source: Id,
     state: &'a mut PlayerState,
     progress_seconds: &'b mut f32,
 }
 
 impl<'a, 'b> Controller<'a, 'b> {
-    pub fn new(state: &'a mut PlayerState, progress_seconds: &'b mut f32) -> Self {
+    /// `id_source` has to be unique to the player, so that the controls of
+    /// different players don't share state.
+    pub fn new(
+        id_source: impl Hash,
+        state: &'a mut PlayerState,
+        progress_seconds: &'b mut f32,
+    ) -> Self {
         Self {
+            id_source: Id::new(id_source),
             state,
             progress_seconds,
         }
     }
 
+    /// Two rows: transport and timeline, then playback options.
     pub fn size_hint(ui: &egui::Ui) -> Vec2 {
         let width = ui.available_size_before_wrap().x.at_least(96.0);
-        let height = ui.spacing().interact_size.y;
+        let height = 2.0 * ui.spacing().interact_size.y + ui.spacing().item_spacing.y;
         vec2(width, height)
     }
 }
 
 impl<'a, 'b> Widget for Controller<'a, 'b> {
     fn ui(self, ui: &mut egui::Ui) -> Response {
-        let current_time = ui.input(|i| i.time);
-        *self.progress_seconds = self.state.progress(current_time) as _;
-        let secs = *self.progress_seconds;
-
-        let PlayerState {
-            is_playing,
-            last_pause_time,
-        } = self.state;
+        let state = self.state;
+        let current_time = state.tick(ui.input(|i| i.time));
+        state.update(current_time);
 
         let sz = Self::size_hint(ui);
+        let row = vec2(sz.x, ui.spacing().interact_size.y);
+
+        let response = ui
+            .allocate_ui_with_layout(sz, Layout::top_down(Align::LEFT), |ui| {
+                ui.push_id(self.id_source, |ui| {
+                    ui.allocate_ui_with_layout(row, Layout::left_to_right(Align::Center), |ui| {
+                        transport_ui(ui, state, current_time);
+                    });
+                    ui.allocate_ui_with_layout(row, Layout::left_to_right(Align::Center), |ui| {
+                        options_ui(ui, state, current_time);
+                    });
+                });
+            })
+            .response;
+
+        *self.progress_seconds = state.progress(current_time) as _;
+        // Keep frames coming while playing. When every player is paused
+        // nothing asks, so the app goes idle.
+        if let Some(after) = state.repaint_after() {
+            ui.ctx().request_repaint_after(after);
+        }
+        response
+    }
+}
+
+// Step back, play/pause, step forward, the timeline and the duration.
+fn transport_ui(ui: &mut egui::Ui, state: &mut PlayerState, current_time: f64) {
+    let height = ui.spacing().interact_size.y;
+    if ui
+        .add_sized(
+            Vec2::splat(height),
+            ImageButton::new(include_image!("assets/step-backward-solid.svg")),
+        )
+        .on_hover_text("previous frame")
+        .clicked()
+    {
+        state.step(current_time, -1);
+    }
+    let is_playing = state.is_playing();
+    if ui
+        .add_sized(
+            Vec2::splat(height),
+            ImageButton::new(if is_playing {
+                include_image!("assets/pause-solid.svg")
+            } else {
+                include_image!("assets/play-solid.svg")
+            }),
+        )
+        .clicked()
+    {
+        state.set_playing(current_time, !is_playing);
+    }
+    if ui
+        .add_sized(
+            Vec2::splat(height),
+            ImageButton::new(include_image!("assets/step-forward-solid.svg")),
+        )
+        .on_hover_text("next frame")
+        .clicked()
+    {
+        state.step(current_time, 1);
+    }
+
+    // Right to left, so the timeline takes whatever the duration field
+    // leaves.
+    ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+        duration_ui(ui, state, current_time);
+        timeline_ui(ui, state, current_time);
+    });
+}
+
+fn duration_ui(ui: &mut egui::Ui, state: &mut PlayerState, current_time: f64) {
+    let mut duration = state.duration_seconds();
+    let changed = ui
+        .add(
+            DragValue::new(&mut duration)
+                .clamp_range(PlayerState::MIN_DURATION_SECONDS..=3600.0)
+                .speed(0.1)
+                .suffix(" s"),
+        )
+        .on_hover_text("duration")
+        .changed();
+    if changed {
+        state.set_duration_seconds(current_time, duration);
+    }
+}
+
+// A progress bar that seeks when clicked or dragged, with the loop range
+// outlined.
+fn timeline_ui(ui: &mut egui::Ui, state: &mut PlayerState, current_time: f64) {
+    let (rect, timeline) = ui.allocate_exact_size(
+        vec2(ui.available_width(), ui.spacing().interact_size.y),
+        Sense::click_and_drag(),
+    );
+    let timeline = timeline.on_hover_cursor(CursorIcon::PointingHand);
+    let duration = state.duration_seconds();
+    let x_range = rect.left() as f64..=rect.right() as f64;
+    if let Some(pointer) = timeline.interact_pointer_pos() {
+        let position = remap_clamp(pointer.x as f64, x_range.clone(), 0.0..=duration);
+        state.seek(current_time, position);
+    }
+    let secs = state.progress(current_time);
+    ui.put(
+        rect,
+        ProgressBar::new((secs / duration) as f32).text(format!("{secs:2.2} s")),
+    );
+    let [start, end] = state.loop_range();
+    if [start, end] != [0.0, duration] {
+        let x = |t: f64| remap_clamp(t, 0.0..=duration, x_range.clone()) as f32;
+        let range = egui::Rect::from_x_y_ranges(x(start)..=x(end), rect.y_range());
+        ui.painter()
+            .rect_stroke(range, 2.0, ui.visuals().selection.stroke);
+    }
+}
+
+// Speed, direction, loop mode and range.
+fn options_ui(ui: &mut egui::Ui, state: &mut PlayerState, current_time: f64) {
+    let mut speed = state.speed().abs();
+    let mut reverse = state.speed() < 0.0;
+    let changed = ui
+        .add(
+            DragValue::new(&mut speed)
+                .clamp_range(PlayerState::SPEED_RANGE)
+                .speed(0.01)
+                .max_decimals(2)
+                .suffix("×"),
+        )
+        .on_hover_text("speed")
+        .changed()
+        | ui.toggle_value(&mut reverse, "reverse").changed();
+    if changed {
+        state.set_speed(current_time, if reverse { -speed } else { speed });
+    }
+
+    let mut loop_mode = state.loop_mode();
+    egui::ComboBox::from_id_source("loop mode")
+        .selected_text(loop_mode.name())
+        .width(80.0)
+        .show_ui(ui, |ui| {
+            for mode in LoopMode::ALL {
+                ui.selectable_value(&mut loop_mode, mode, mode.name());
+            }
+        });
+    if loop_mode != state.loop_mode() {
+        state.set_loop_mode(current_time, loop_mode);
+    }
+
+    let position = state.progress(current_time);
+    let [start, end] = state.loop_range();
+    if ui
+        .button("[")
+        .on_hover_text("start the loop range here")
+        .clicked()
+    {
+        state.set_loop_range(current_time, Some([position, end]));
+    }
+    if ui
+        .button("]")
+        .on_hover_text("end the loop range here")
+        .clicked()
+    {
+        state.set_loop_range(current_time, Some([start, position]));
+    }
+    if ui
+        .add_enabled(state.loop_range.is_some(), egui::Button::new("clear"))
+        .on_hover_text("loop over the whole clip")
+        .clicked()
+    {
+        state.set_loop_range(current_time, None);
+    }
+
+    clock_ui(ui, state);
+
+    let mut max_fps = state.max_fps();
+    if ui
+        .add(
+            DragValue::new(&mut max_fps)
+                .clamp_range(PlayerState::MAX_FPS_RANGE)
+                .max_decimals(0)
+                .prefix("max ")
+                .suffix(" fps"),
+        )
+        .on_hover_text("most frames per second to paint while playing")
+        .changed()
+    {
+        state.set_max_fps(max_fps);
+    }
+}
+
+fn clock_ui(ui: &mut egui::Ui, state: &mut PlayerState) {
+    let mut clock = state.clock();
+    egui::ComboBox::from_id_source("clock")
+        .selected_text(clock.name())
+        .width(80.0)
+        .show_ui(ui, |ui| {
+            for option in [
+                Clock::WallClock,
+                Clock::FixedStep {
+                    step_seconds: Clock::DEFAULT_STEP_SECONDS,
+                },
+            ] {
+                if ui
+                    .selectable_label(option.name() == clock.name(), option.name())
+                    .clicked()
+                    && option.name() != clock.name()
+                {
+                    clock = option;
+                }
+            }
+        });
+    if let Clock::FixedStep { step_seconds } = &mut clock {
+        let mut fps = 1.0 / *step_seconds;
+        if ui
+            .add(
+                DragValue::new(&mut fps)
+                    .clamp_range(Clock::FPS_RANGE)
+                    .max_decimals(0)
+                    .suffix(" fps"),
+            )
+            .on_hover_text("frames per second of playback")
+            .changed()
+        {
+            *step_seconds = 1.0 / fps;
+        }
+    }
+    if clock != state.clock() {
+        state.set_clock(clock);
+    }
+}
 
-        ui.allocate_ui_with_layout(sz, Layout::left_to_right(Align::LEFT), |ui| {
-            if ui
-                .add_sized(
-                    Vec2::splat(sz.y),
-                    ImageButton::new(if *is_playing {
-                        include_image!("assets/pause-solid.svg")
-                    } else {
-                        include_image!("assets/play-solid.svg")
-                    }),
-                )
-                .clicked()
-            {
-                *is_playing = !*is_playing;
-                *last_pause_time = Some(current_time - (last_pause_time.unwrap_or(0.0)));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seek_while_paused() {
+        let mut player = PlayerState::default();
+        player.seek(100.0, 3.0);
+        assert_eq!(player.progress(100.0), 3.0);
+        assert_eq!(player.progress(250.0), 3.0);
+    }
+
+    #[test]
+    fn seek_while_playing() {
+        let mut player = PlayerState::default();
+        player.set_playing(100.0, true);
+        player.seek(105.0, 2.0);
+        assert_eq!(player.progress(105.0), 2.0);
+        assert_eq!(player.progress(106.5), 3.5);
+    }
+
+    #[test]
+    fn pause_and_resume_keep_the_position() {
+        let mut player = PlayerState::default();
+        player.set_playing(10.0, true);
+        player.set_playing(14.0, false);
+        assert_eq!(player.progress(20.0), 4.0);
+        player.set_playing(30.0, true);
+        assert_eq!(player.progress(31.0), 5.0);
+    }
+
+    #[test]
+    fn playback_loops_over_the_duration() {
+        let mut player = PlayerState::default();
+        player.set_duration_seconds(0.0, 4.0);
+        player.set_playing(0.0, true);
+        assert_eq!(player.progress(5.0), 1.0);
+        // seeks are clamped to the clip
+        player.seek(5.0, 7.0);
+        assert_eq!(player.progress(5.0), 4.0);
+        assert_eq!(player.progress(5.5), 0.5);
+        player.seek(5.0, -1.0);
+        assert_eq!(player.progress(5.0), 0.0);
+    }
+
+    #[test]
+    fn shorter_duration_wraps_the_position() {
+        let mut player = PlayerState::default();
+        player.seek(0.0, 7.0);
+        player.set_duration_seconds(0.0, 5.0);
+        assert_eq!(player.progress(0.0), 2.0);
+        player.set_duration_seconds(0.0, 0.0);
+        assert_eq!(player.duration_seconds(), PlayerState::MIN_DURATION_SECONDS);
+    }
+
+    #[test]
+    fn speed_changes_keep_the_position() {
+        let mut player = PlayerState::default();
+        player.set_playing(0.0, true);
+        player.set_speed(2.0, 0.5);
+        assert_eq!(player.progress(2.0), 2.0);
+        assert_eq!(player.progress(4.0), 3.0);
+        player.set_speed(4.0, -2.0);
+        assert_eq!(player.progress(5.0), 1.0);
+        // reverse loops back round from the start to the end
+        assert_eq!(player.progress(5.75), 9.5);
+    }
+
+    #[test]
+    fn speed_is_clamped_but_keeps_its_direction() {
+        let mut player = PlayerState::default();
+        player.set_speed(0.0, 100.0);
+        assert_eq!(player.speed(), 10.0);
+        player.set_speed(0.0, -0.01);
+        assert_eq!(player.speed(), -0.1);
+    }
+
+    #[test]
+    fn once_stops_at_the_end_and_starts_over() {
+        let mut player = PlayerState::default();
+        player.set_loop_mode(0.0, LoopMode::Once);
+        player.set_playing(0.0, true);
+        assert!(!player.is_finished(9.0));
+        assert_eq!(player.progress(12.0), 10.0);
+        assert!(player.is_finished(12.0));
+        player.update(12.0);
+        assert!(!player.is_playing());
+        assert_eq!(player.progress(20.0), 10.0);
+        player.set_playing(20.0, true);
+        assert_eq!(player.progress(21.0), 1.0);
+    }
+
+    #[test]
+    fn once_in_reverse_stops_at_the_start() {
+        let mut player = PlayerState::default();
+        player.set_loop_mode(0.0, LoopMode::Once);
+        player.seek(0.0, 3.0);
+        player.set_speed(0.0, -1.0);
+        player.set_playing(0.0, true);
+        assert_eq!(player.progress(5.0), 0.0);
+        player.update(5.0);
+        assert!(!player.is_playing());
+        player.set_playing(5.0, true);
+        assert_eq!(player.progress(6.0), 9.0);
+    }
+
+    #[test]
+    fn ping_pong_turns_around_at_the_ends() {
+        let mut player = PlayerState::default();
+        player.set_loop_mode(0.0, LoopMode::PingPong);
+        player.set_playing(0.0, true);
+        assert_eq!(player.progress(8.0), 8.0);
+        assert_eq!(player.progress(12.0), 8.0);
+        assert_eq!(player.progress(21.0), 1.0);
+        // pausing on the way back keeps going back after resuming
+        player.set_playing(12.0, false);
+        player.set_playing(30.0, true);
+        assert_eq!(player.progress(31.0), 7.0);
+    }
+
+    #[test]
+    fn playback_stays_in_the_loop_range() {
+        let mut player = PlayerState::default();
+        player.seek(0.0, 5.0);
+        player.set_loop_range(0.0, Some([6.0, 2.0]));
+        assert_eq!(player.loop_range(), [2.0, 6.0]);
+        assert_eq!(player.progress(0.0), 5.0);
+        player.set_playing(0.0, true);
+        assert_eq!(player.progress(2.0), 3.0);
+        player.seek(2.0, 9.0);
+        assert_eq!(player.progress(2.0), 6.0);
+        // ranges that no longer fit the clip are dropped
+        player.set_duration_seconds(2.0, 2.0);
+        assert_eq!(player.loop_range(), [0.0, 2.0]);
+        player.set_duration_seconds(2.0, 10.0);
+        player.set_loop_range(2.0, None);
+        assert_eq!(player.loop_range(), [0.0, 10.0]);
+    }
+
+    #[test]
+    fn wall_clock_follows_input_time() {
+        let mut player = PlayerState::default();
+        assert_eq!(player.tick(100.0), 0.0);
+        assert_eq!(player.tick(100.5), 0.5);
+        assert_eq!(player.tick(100.5), 0.5);
+        assert_eq!(player.tick(102.0), 2.0);
+    }
+
+    // Frame times jitter, but fixed-step playback shows the same frames.
+    #[test]
+    fn fixed_step_ignores_frame_times() {
+        let frames = |input_times: &[f64]| {
+            let mut player = PlayerState::default();
+            player.set_clock(Clock::FixedStep { step_seconds: 0.25 });
+            let now = player.tick(input_times[0]);
+            player.set_playing(now, true);
+            input_times[1..]
+                .iter()
+                .map(|&t| {
+                    let now = player.tick(t);
+                    player.progress(now)
+                })
+                .collect::<Vec<_>>()
+        };
+        let smooth = frames(&[0.0, 1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(smooth, [0.25, 0.5, 0.75, 1.0]);
+        assert_eq!(frames(&[7.0, 7.1, 9.0, 9.01, 30.0]), smooth);
+    }
+
+    #[test]
+    fn switching_clocks_doesnt_move_playback() {
+        let mut player = PlayerState::default();
+        player.tick(50.0);
+        player.set_playing(player.now(), true);
+        let now = player.tick(52.0);
+        assert_eq!(player.progress(now), 2.0);
+        player.set_clock(Clock::FixedStep { step_seconds: 0.5 });
+        let now = player.tick(60.0);
+        assert_eq!(player.progress(now), 2.5);
+        player.set_clock(Clock::WallClock);
+        let now = player.tick(61.0);
+        assert_eq!(player.progress(now), 3.5);
+    }
+
+    #[test]
+    fn steps_move_a_frame_at_a_time() {
+        let mut player = PlayerState::default();
+        player.set_clock(Clock::FixedStep { step_seconds: 0.5 });
+        player.set_playing(0.0, true);
+        player.step(4.0, 1);
+        assert!(!player.is_playing());
+        assert_eq!(player.progress(4.0), 4.5);
+        player.step(4.0, -3);
+        assert_eq!(player.progress(4.0), 3.0);
+        // scaled by the speed, but always forward for positive frames
+        player.set_speed(4.0, -0.5);
+        player.step(4.0, 1);
+        assert_eq!(player.progress(4.0), 3.25);
+        // and not past the ends
+        player.step(4.0, -100);
+        assert_eq!(player.progress(4.0), 0.0);
+    }
+
+    // Saves in one session and loads in another, where egui's input time
+    // starts over.
+    fn restart(player: &PlayerState) -> PlayerState {
+        let saved = ron::to_string(player).unwrap();
+        ron::from_str(&saved).unwrap()
+    }
+
+    #[test]
+    fn playing_player_resumes_where_it_was_saved() {
+        let mut player = PlayerState::default();
+        player.tick(1000.0);
+        player.set_playing(player.now(), true);
+        player.set_speed(player.now(), 0.5);
+        let now = player.tick(1006.0);
+        assert_eq!(player.progress(now), 3.0);
+
+        let mut player = restart(&player);
+        assert!(player.is_playing());
+        assert_eq!(player.speed(), 0.5);
+        let now = player.tick(2.0);
+        assert_eq!(player.progress(now), 3.0);
+        let now = player.tick(4.0);
+        assert_eq!(player.progress(now), 4.0);
+    }
+
+    #[test]
+    fn paused_player_stays_where_it_was_saved() {
+        let mut player = PlayerState::default();
+        player.tick(500.0);
+        player.set_loop_mode(player.now(), LoopMode::PingPong);
+        player.set_loop_range(player.now(), Some([1.0, 8.0]));
+        player.set_clock(Clock::FixedStep { step_seconds: 0.1 });
+        player.set_max_fps(30.0);
+        player.seek(player.now(), 6.5);
+
+        let mut player = restart(&player);
+        assert_eq!(player.max_fps(), 30.0);
+        assert!(!player.is_playing());
+        assert_eq!(player.loop_mode(), LoopMode::PingPong);
+        assert_eq!(player.loop_range(), [1.0, 8.0]);
+        assert_eq!(player.clock(), Clock::FixedStep { step_seconds: 0.1 });
+        for t in [0.0, 1.0, 100.0] {
+            let now = player.tick(t);
+            assert_eq!(player.progress(now), 6.5);
+        }
+    }
+
+    #[test]
+    fn round_trip_is_stable() {
+        let mut player = PlayerState::default();
+        player.tick(42.0);
+        player.set_playing(player.now(), true);
+        player.tick(44.5);
+        let saved = ron::to_string(&player).unwrap();
+        assert_eq!(ron::to_string(&restart(&player)).unwrap(), saved);
+    }
+
+    #[test]
+    fn loads_state_saved_before_positions() {
+        let mut player: PlayerState =
+            ron::from_str("(is_playing: true, last_pause_time: Some(123.0))").unwrap();
+        assert!(player.is_playing());
+        let now = player.tick(7.0);
+        assert_eq!(player.progress(now), 0.0);
+        assert_eq!(player.duration_seconds(), 10.0);
+    }
+
+    #[test]
+    fn bad_saved_values_are_clamped() {
+        let player: PlayerState = ron::from_str(
+            "(position: 50.0, speed: 0.0, duration_seconds: -1.0, \
+             clock: FixedStep(step_seconds: 0.0))",
+        )
+        .unwrap();
+        assert_eq!(player.speed(), 0.1);
+        assert_eq!(
+            player.clock(),
+            Clock::FixedStep {
+                step_seconds: 1.0 / 240.0
+            }
+        );
+        assert_eq!(player.duration_seconds(), PlayerState::MIN_DURATION_SECONDS);
+        assert_eq!(
+            player.progress(player.now()),
+            PlayerState::MIN_DURATION_SECONDS
+        );
+    }
+
+    #[test]
+    fn fixed_steps_are_clamped() {
+        let mut player = PlayerState::default();
+        player.set_clock(Clock::FixedStep { step_seconds: -1.0 });
+        assert_eq!(
+            player.clock(),
+            Clock::FixedStep {
+                step_seconds: 1.0 / 240.0
             }
-            ui.add(
-                ProgressBar::new((secs / 10.0).fract() as f32)
-                    .animate(*is_playing)
-                    .text(format!("{secs:2.2} s")),
-            );
-        })
-        .response
+        );
+        player.set_clock(Clock::FixedStep { step_seconds: 5.0 });
+        assert_eq!(player.clock(), Clock::FixedStep { step_seconds: 1.0 });
+    }
+
+    #[test]
+    fn repaints_only_while_playing() {
+        let mut player = PlayerState::default();
+        assert_eq!(player.repaint_after(), None);
+        player.set_playing(0.0, true);
+        player.set_max_fps(20.0);
+        assert_eq!(player.repaint_after(), Some(Duration::from_millis(50)));
+        player.set_max_fps(0.0);
+        assert_eq!(player.repaint_after(), Some(Duration::from_secs(1)));
+        // finishing a single play through goes idle
+        player.set_loop_mode(0.0, LoopMode::Once);
+        player.update(11.0);
+        assert_eq!(player.repaint_after(), None);
     }
 }
@@ -0,0 +1,62 @@
+//! Saving images as PNGs: to a file on native, as a download on the web.
+
+use egui::ColorImage;
+
+/// Encodes `image` as a PNG with straight (not premultiplied) alpha.
+#[cfg(target_arch = "wasm32")]
+fn encode(image: &ColorImage) -> Result<Vec<u8>, image::ImageError> {
+    let mut bytes = Vec::new();
+    to_rgba(image).write_to(
+        &mut std::io::Cursor::new(&mut bytes),
+        image::ImageOutputFormat::Png,
+    )?;
+    Ok(bytes)
+}
+
+/// Saves `image` as a PNG at `path`.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save(
+    image: &ColorImage,
+    path: impl AsRef<std::path::Path>,
+) -> Result<(), image::ImageError> {
+    to_rgba(image).save_with_format(path, image::ImageFormat::Png)
+}
+
+/// Offers `image` to the user as a PNG download named `file_name`.
+#[cfg(target_arch = "wasm32")]
+pub fn download(image: &ColorImage, file_name: &str) -> Result<(), String> {
+    use wasm_bindgen::JsCast;
+
+    let bytes = encode(image).map_err(|e| e.to_string())?;
+    let js = |e: wasm_bindgen::JsValue| format!("{e:?}");
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes.as_slice()));
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(
+        &parts,
+        web_sys::BlobPropertyBag::new().type_("image/png"),
+    )
+    .map_err(js)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(js)?;
+    let document = web_sys::window()
+        .and_then(|w| w.document())
+        .ok_or("no document")?;
+    let anchor: web_sys::HtmlAnchorElement = document
+        .create_element("a")
+        .map_err(js)?
+        .dyn_into()
+        .map_err(|_| "not an anchor element")?;
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+    web_sys::Url::revoke_object_url(&url).map_err(js)
+}
+
+fn to_rgba(image: &ColorImage) -> image::RgbaImage {
+    let [width, height] = image.size;
+    let rgba = image
+        .pixels
+        .iter()
+        .flat_map(|p| p.to_srgba_unmultiplied())
+        .collect();
+    image::RgbaImage::from_raw(width as u32, height as u32, rgba)
+        .expect("the buffer should match the image size")
+}
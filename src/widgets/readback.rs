@@ -0,0 +1,177 @@
+//! Copying textures back to the CPU.
+
+use eframe::wgpu::{
+    self, Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device, Texture, TextureFormat,
+};
+use egui::ColorImage;
+
+/// A copy of a texture in a buffer that can be mapped for reading.
+///
+/// Record the copy with [`Readback::new`], submit the encoder, then call
+/// [`Readback::read`] (native) or [`Readback::read_async`] (web).
+pub struct Readback {
+    buffer: Buffer,
+    width: u32,
+    height: u32,
+    // Rows in the buffer are padded to `COPY_BYTES_PER_ROW_ALIGNMENT`.
+    padded_bytes_per_row: u32,
+    premultiplied: bool,
+}
+
+impl Readback {
+    const BYTES_PER_PIXEL: u32 = 4;
+
+    /// Records a copy of `texture` into a new buffer.
+    ///
+    /// The texture must have `COPY_SRC` usage and an 8-bit RGBA format.
+    /// `premultiplied` says whether its colors have premultiplied alpha.
+    pub fn new(
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        texture: &Texture,
+        premultiplied: bool,
+    ) -> Self {
+        debug_assert!(matches!(
+            texture.format(),
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb
+        ));
+        let size = texture.size();
+        let unpadded = size.width * Self::BYTES_PER_PIXEL;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (unpadded + align - 1) / align * align;
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("readback buffer"),
+            size: (padded_bytes_per_row * size.height) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        Self {
+            buffer,
+            width: size.width,
+            height: size.height,
+            padded_bytes_per_row,
+            premultiplied,
+        }
+    }
+
+    /// Waits for the copy to finish and returns the pixels.
+    ///
+    /// Blocks until the GPU is done, so only call this after the encoder the
+    /// copy was recorded into has been submitted.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read(self, device: &Device) -> Result<ColorImage, wgpu::BufferAsyncError> {
+        puffin::profile_function!();
+        let slice = self.buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).ok();
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .expect("map_async callback should run before poll(Wait) returns")?;
+        Ok(self.to_image())
+    }
+
+    /// Returns the pixels once the copy is done.
+    ///
+    /// Browsers can't block on the GPU, so the buffer is mapped
+    /// asynchronously. Only call this after the encoder the copy was recorded
+    /// into has been submitted.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn read_async(self) -> Result<ColorImage, wgpu::BufferAsyncError> {
+        let (sender, receiver) = oneshot::channel();
+        self.buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| sender.send(result));
+        receiver.await?;
+        Ok(self.to_image())
+    }
+
+    // The buffer must be mapped.
+    fn to_image(&self) -> ColorImage {
+        let width = self.width as usize;
+        let row_bytes = width * Self::BYTES_PER_PIXEL as usize;
+        let mut rgba = Vec::with_capacity(row_bytes * self.height as usize);
+        {
+            let data = self.buffer.slice(..).get_mapped_range();
+            for row in data.chunks_exact(self.padded_bytes_per_row as usize) {
+                rgba.extend_from_slice(&row[..row_bytes]);
+            }
+        }
+        self.buffer.unmap();
+        let size = [width, self.height as usize];
+        if self.premultiplied {
+            ColorImage::from_rgba_premultiplied(size, &rgba)
+        } else {
+            ColorImage::from_rgba_unmultiplied(size, &rgba)
+        }
+    }
+}
+
+/// Just enough of a oneshot channel to await a `map_async` callback.
+#[cfg(target_arch = "wasm32")]
+mod oneshot {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll, Waker},
+    };
+
+    struct Shared<T> {
+        value: Option<T>,
+        waker: Option<Waker>,
+    }
+
+    pub struct Sender<T>(Arc<Mutex<Shared<T>>>);
+    pub struct Receiver<T>(Arc<Mutex<Shared<T>>>);
+
+    pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+        let shared = Arc::new(Mutex::new(Shared {
+            value: None,
+            waker: None,
+        }));
+        (Sender(shared.clone()), Receiver(shared))
+    }
+
+    impl<T> Sender<T> {
+        pub fn send(self, value: T) {
+            let waker = {
+                let mut shared = self.0.lock().unwrap();
+                shared.value = Some(value);
+                shared.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    impl<T> Future for Receiver<T> {
+        type Output = T;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+            let mut shared = self.0.lock().unwrap();
+            match shared.value.take() {
+                Some(value) => Poll::Ready(value),
+                None => {
+                    shared.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+}
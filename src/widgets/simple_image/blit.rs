@@ -0,0 +1,144 @@
+use eframe::egui_wgpu::wgpu;
+use wgpu::{
+    AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BlendState,
+    ColorTargetState, ColorWrites, Device, FilterMode, FragmentState, MultisampleState,
+    PipelineLayoutDescriptor, PrimitiveState, RenderPass, RenderPipeline, RenderPipelineDescriptor,
+    Sampler, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource,
+    ShaderStages, Texture, TextureFormat, TextureSampleType, TextureViewDescriptor,
+    TextureViewDimension, VertexState,
+};
+
+/// Draws a texture so that it fills the viewport of a paint callback.
+pub struct BlitPipeline {
+    pipeline: RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    sampler: Sampler,
+    target_format: TextureFormat,
+}
+
+impl BlitPipeline {
+    pub fn new(device: &Device, target_format: TextureFormat) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("simple_image blit bind group layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("simple_image blit sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let module = &device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("simple_image blit shader module"),
+            source: ShaderSource::Wgsl(include_str!("blit.wgsl").into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("simple_image blit pipeline"),
+            layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("simple_image blit pipeline layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            })),
+            vertex: VertexState {
+                module,
+                entry_point: "vs",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module,
+                entry_point: "fs",
+                targets: &[Some(ColorTargetState {
+                    format: target_format,
+                    blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            target_format,
+        }
+    }
+
+    /// The view formats a texture of `format` has to be created with so it
+    /// can be passed to [`Self::bind`].
+    ///
+    /// Reinterpreting a texture's format isn't supported everywhere (e.g. on
+    /// GL), so it's only asked for when the target needs it.
+    pub fn view_formats(&self, format: TextureFormat) -> Vec<TextureFormat> {
+        if self.target_format.is_srgb() {
+            vec![format.add_srgb_suffix()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Binds `texture` for drawing.
+    ///
+    /// The texture's bytes are copied through unchanged. For an sRGB target
+    /// the texture is read through an sRGB view so the decode on sampling and
+    /// the encode on writing cancel out.
+    pub fn bind(&self, device: &Device, texture: &Texture) -> BindGroup {
+        let format = if self.target_format.is_srgb() {
+            texture.format().add_srgb_suffix()
+        } else {
+            texture.format()
+        };
+        let view = texture.create_view(&TextureViewDescriptor {
+            label: Some("simple_image blit texture view"),
+            format: Some(format),
+            ..Default::default()
+        });
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("simple_image blit bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+
+    pub fn paint<'rp>(&'rp self, bind_group: &'rp BindGroup, pass: &mut RenderPass<'rp>) {
+        puffin::profile_function!();
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,26 @@
+@group(0) @binding(0)
+var image: texture_2d<f32>;
+
+@group(0) @binding(1)
+var image_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle that covers the viewport.
+// uv is (0,0) at the top-left of the viewport and (1,1) at the bottom-right.
+@vertex
+fn vs(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.position = vec4(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(image, image_sampler, in.uv);
+}
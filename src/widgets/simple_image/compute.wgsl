@@ -10,10 +10,17 @@ var out: texture_storage_2d<rgba8unorm, write>;
 @binding(1)
 var<uniform> settings:Settings;
 
+// The painter dispatches enough workgroups of this size to cover the texture.
 @compute 
-@workgroup_size(1)
+@workgroup_size(8, 8)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    // The dispatch is rounded up to whole workgroups, so some invocations
+    // fall outside the texture.
+    if any(global_id.xy >= textureDimensions(out)) {
+        return;
+    }
     let r = vec2<i32>(global_id.xy);
-    let c = vec4(vec2<f32>(r) * 1.0e-2, 1.0, 1.0);
+    let p = vec2<f32>(r) * 1.0e-2;
+    let c = vec4(0.5 + 0.5 * cos(settings.time + p.xyx + vec3(0.0, 2.0, 4.0)), 1.0);
     textureStore(out, r, c);
 }
\ No newline at end of file
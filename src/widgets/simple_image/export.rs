@@ -0,0 +1,48 @@
+//! Rendering the simple image without a window.
+
+use std::path::Path;
+
+use eframe::wgpu::{CommandEncoderDescriptor, Device, Queue};
+use egui::ColorImage;
+
+pub use crate::widgets::export::ExportError;
+use crate::widgets::{export, gpu, png};
+
+use super::{painter::Painter, shader::ComputeShader, Settings};
+
+/// Runs the built-in compute shader with `settings` on a `width` x `height`
+/// texture and reads the result back. Fails if either side is 0 or larger
+/// than `device` supports.
+pub fn render(
+    device: &Device,
+    queue: &Queue,
+    settings: &Settings,
+    width: u32,
+    height: u32,
+) -> Result<ColorImage, ExportError> {
+    puffin::profile_function!();
+    export::check_size(device, width, height)?;
+    // No blit, so the texture never needs another view format.
+    let mut painter = Painter::new(device, queue, &ComputeShader::default(), &[], width, height);
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("simple_image export encoder"),
+    });
+    painter.refresh(device, queue, &mut encoder, settings);
+    queue.submit(Some(encoder.finish()));
+    painter
+        .read_pixels(device, queue)
+        .map_err(ExportError::Readback)
+}
+
+/// Renders with [`render`] on a [headless device](gpu::headless_device) and
+/// saves the result as a PNG at `path`.
+pub fn save_png(
+    settings: &Settings,
+    width: u32,
+    height: u32,
+    path: impl AsRef<Path>,
+) -> Result<(), ExportError> {
+    let (device, queue) = gpu::headless_device().ok_or(ExportError::NoDevice)?;
+    let image = render(&device, &queue, settings, width, height)?;
+    png::save(&image, path).map_err(ExportError::Image)
+}
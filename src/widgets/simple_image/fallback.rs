@@ -0,0 +1,20 @@
+//! CPU version of `compute.wgsl` for when wgpu isn't available.
+
+use egui::{Color32, ColorImage};
+
+use super::painter::Settings;
+
+pub fn render(settings: &Settings, width: u32, height: u32) -> ColorImage {
+    puffin::profile_function!();
+    // rgba8unorm storage clamps to [0,1]
+    let u8 = |v: f32| (255.0 * v.clamp(0.0, 1.0)).round() as u8;
+    let channel = |p: f32, offset: f32| u8(0.5 + 0.5 * (settings.time + p + offset).cos());
+    let pixels = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x as f32 * 1.0e-2, y as f32 * 1.0e-2)))
+        .map(|(x, y)| Color32::from_rgb(channel(x, 0.0), channel(y, 2.0), channel(x, 4.0)))
+        .collect();
+    ColorImage {
+        size: [width as _, height as _],
+        pixels,
+    }
+}
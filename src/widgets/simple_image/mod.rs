@@ -1,2 +1,10 @@
+mod blit;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod export;
+mod fallback;
 mod painter;
+mod shader;
+mod timer;
 pub mod ui;
+
+pub use painter::Settings;
@@ -1,17 +1,32 @@
+use std::collections::{
+    hash_map::Entry::{Occupied, Vacant},
+    HashMap,
+};
+
 use eframe::{
-    egui_wgpu::{wgpu, RenderState},
+    egui_wgpu::wgpu,
     wgpu::{
-        BindGroup, Buffer, CommandEncoderDescriptor, ComputePassDescriptor, Queue, TextureFormat,
-        TextureView,
+        BindGroup, Buffer, BufferAsyncError, CommandEncoder, CommandEncoderDescriptor,
+        ComputePassDescriptor, Device, Queue,
     },
 };
+use egui::ColorImage;
+use log::{debug, trace};
 use serde::{Deserialize, Serialize};
 use wgpu::{
-    BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
-    BindingType, BufferDescriptor, BufferUsages, ComputePass, ComputePipeline,
-    ComputePipelineDescriptor, PipelineLayoutDescriptor, ShaderModuleDescriptor, ShaderSource,
-    ShaderStages, StorageTextureAccess, Texture, TextureDescriptor, TextureUsages,
-    TextureViewDescriptor, TextureViewDimension,
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, BufferDescriptor, BufferUsages, ComputePass,
+    ComputePipeline, ComputePipelineDescriptor, PipelineLayout, PipelineLayoutDescriptor,
+    RenderPass, ShaderModuleDescriptor, ShaderSource, ShaderStages, StorageTextureAccess, Texture,
+    TextureDescriptor, TextureFormat, TextureUsages, TextureViewDescriptor, TextureViewDimension,
+};
+
+use crate::widgets::readback::Readback;
+
+use super::{
+    blit::BlitPipeline,
+    shader::{self, ComputeShader},
+    timer::GpuTimer,
 };
 
 unsafe fn as_raw_bytes<T>(x: &T) -> &[u8] {
@@ -19,9 +34,9 @@ unsafe fn as_raw_bytes<T>(x: &T) -> &[u8] {
 }
 
 #[repr(C, align(16))]
-#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Settings {
-    time: f32,
+    pub time: f32,
 }
 
 impl Settings {
@@ -38,14 +53,166 @@ impl Settings {
 #[derive(Debug)]
 pub struct Painter {
     pipeline: ComputePipeline,
+    pipeline_layout: PipelineLayout,
+    // What `pipeline` was compiled from.
+    shader: ComputeShader,
+    // The last shader the device rejected.
+    rejected: Option<ComputeShader>,
+    layout: BindGroupLayout,
     texture: Texture,
+    // Other formats the texture can be viewed as.
+    view_formats: Vec<TextureFormat>,
     uniforms: Buffer,
     bind_group: BindGroup,
+
+    // What the texture was last computed with.
+    computed: Option<Settings>,
+
+    // Only available if the device supports timestamp queries.
+    timer: Option<GpuTimer>,
 }
 
 impl Painter {
-    pub fn new(rc: &RenderState, width: u32, height: u32) -> Option<Self> {
-        let texture = rc.device.create_texture(&TextureDescriptor {
+    /// The storage texture's format
+    pub const FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        shader: &ComputeShader,
+        view_formats: &[TextureFormat],
+        width: u32,
+        height: u32,
+    ) -> Self {
+        // Memory layout for the compute shader
+        // There's just the one output texture
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("simple_image compute shader bind group layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        view_dimension: TextureViewDimension::D2,
+                        access: StorageTextureAccess::WriteOnly,
+                        format: Self::FORMAT,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let uniforms = device.create_buffer(&Settings::descriptor());
+        let texture = Self::create_texture(device, view_formats, width, height);
+        let bind_group = Self::create_bind_group(device, &layout, &texture, &uniforms);
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("simple_image pipeline layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, shader);
+
+        Self {
+            pipeline,
+            pipeline_layout,
+            shader: shader.clone(),
+            rejected: None,
+            layout,
+            texture,
+            view_formats: view_formats.to_vec(),
+            bind_group,
+            uniforms,
+            computed: None,
+            timer: GpuTimer::new(device, queue),
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        layout: &PipelineLayout,
+        shader: &ComputeShader,
+    ) -> ComputePipeline {
+        puffin::profile_function!();
+        let module = &device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("simple_image compute shader module"),
+            source: ShaderSource::Wgsl(shader.source().into()),
+        });
+        device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("simple_image compute pipeline"),
+            layout: Some(layout),
+            module,
+            entry_point: shader::ENTRY_POINT,
+        })
+    }
+
+    /// Recompiles the pipeline if `shader` differs from the one in use. The
+    /// texture is recomputed on the next `refresh`.
+    ///
+    /// `shader` has already been validated, but the device can still reject
+    /// it, so on native the pipeline is created in an error scope and the
+    /// previous one is kept on error. A rejected shader isn't retried.
+    pub fn set_shader(&mut self, device: &Device, shader: &ComputeShader) {
+        if self.shader == *shader || self.rejected.as_ref() == Some(shader) {
+            return;
+        }
+        trace!("simple_image: recompiling compute shader");
+        match Self::try_create_pipeline(device, &self.pipeline_layout, shader) {
+            Ok(pipeline) => {
+                self.pipeline = pipeline;
+                self.shader = shader.clone();
+                self.rejected = None;
+                self.computed = None;
+            }
+            Err(e) => {
+                log::error!("simple_image: shader failed to compile, keeping the old one: {e}");
+                self.rejected = Some(shader.clone());
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn try_create_pipeline(
+        device: &Device,
+        layout: &PipelineLayout,
+        shader: &ComputeShader,
+    ) -> Result<ComputePipeline, wgpu::Error> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = Self::create_pipeline(device, layout, shader);
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(error) => Err(error),
+            None => Ok(pipeline),
+        }
+    }
+
+    // Error scopes can't be waited on synchronously on the web, so this relies
+    // on the validation alone.
+    #[cfg(target_arch = "wasm32")]
+    fn try_create_pipeline(
+        device: &Device,
+        layout: &PipelineLayout,
+        shader: &ComputeShader,
+    ) -> Result<ComputePipeline, wgpu::Error> {
+        Ok(Self::create_pipeline(device, layout, shader))
+    }
+
+    fn create_texture(
+        device: &Device,
+        view_formats: &[TextureFormat],
+        width: u32,
+        height: u32,
+    ) -> Texture {
+        device.create_texture(&TextureDescriptor {
             label: Some("simple_image output texture"),
             size: wgpu::Extent3d {
                 width,
@@ -55,46 +222,23 @@ impl Painter {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
-            view_formats: &[wgpu::TextureFormat::Rgba8UnormSrgb], // maybe add srgb?
-        });
-
-        // Memory layout for the compute shader
-        // There's just the one output texture
-        let layout = rc
-            .device
-            .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("simple_image compute shader bind group layout"),
-                entries: &[
-                    BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::StorageTexture {
-                            view_dimension: TextureViewDimension::D2,
-                            access: StorageTextureAccess::WriteOnly,
-                            format: texture.format(),
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                ],
-            });
-
-        let uniforms = rc.device.create_buffer(&Settings::descriptor());
+            format: Self::FORMAT,
+            usage: TextureUsages::STORAGE_BINDING
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC,
+            view_formats,
+        })
+    }
 
-        let bind_group = rc.device.create_bind_group(&BindGroupDescriptor {
+    fn create_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        texture: &Texture,
+        uniforms: &Buffer,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
             label: Some("simple_image bind group"),
-            layout: &layout,
+            layout,
             entries: &[
                 BindGroupEntry {
                     binding: 0,
@@ -110,34 +254,22 @@ impl Painter {
                     resource: uniforms.as_entire_binding(),
                 },
             ],
-        });
+        })
+    }
 
-        let module = &rc.device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("My Painter shader module"),
-            source: ShaderSource::Wgsl(include_str!("compute.wgsl").into()),
-        });
+    /// Texture size in pixels.
+    pub fn size(&self) -> [u32; 2] {
+        let extent = self.texture.size();
+        [extent.width, extent.height]
+    }
 
-        let pipeline = rc
-            .device
-            .create_compute_pipeline(&ComputePipelineDescriptor {
-                label: Some("simple_image compute pipeline"),
-                layout: Some(
-                    &rc.device.create_pipeline_layout(&PipelineLayoutDescriptor {
-                        label: Some("simple_image pipeline layout"),
-                        bind_group_layouts: &[&layout],
-                        push_constant_ranges: &[],
-                    }),
-                ),
-                module,
-                entry_point: "main",
-            });
-
-        Some(Self {
-            pipeline,
-            texture,
-            bind_group,
-            uniforms,
-        })
+    /// Reallocates the output texture. Its contents are recomputed on the
+    /// next `refresh`.
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.texture = Self::create_texture(device, &self.view_formats, width, height);
+        self.bind_group =
+            Self::create_bind_group(device, &self.layout, &self.texture, &self.uniforms);
+        self.computed = None;
     }
 
     pub fn update(&self, queue: &Queue, settings: &Settings) {
@@ -145,32 +277,189 @@ impl Painter {
         queue.write_buffer(&self.uniforms, 0, unsafe { as_raw_bytes(settings) });
     }
 
-    pub fn create_texture_view(&self) -> TextureView {
-        self.texture.create_view(&TextureViewDescriptor {
-            label: Some("simple_image texture view"),
-            format: Some(TextureFormat::Rgba8UnormSrgb),
-            ..Default::default()
-        })
+    pub fn texture(&self) -> &Texture {
+        &self.texture
     }
 
-    pub fn compute<'rp>(&'rp self, pass: &mut ComputePass<'rp>) {
+    // Records and submits a copy of the texture.
+    fn start_readback(&self, device: &Device, queue: &Queue) -> Readback {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("simple_image readback encoder"),
+        });
+        // The compute shader writes straight alpha.
+        let readback = Readback::new(device, &mut encoder, &self.texture, false);
+        queue.submit(Some(encoder.finish()));
+        readback
+    }
+
+    /// Copies the texture's pixels back to the CPU, blocking until done.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_pixels(
+        &self,
+        device: &Device,
+        queue: &Queue,
+    ) -> Result<ColorImage, BufferAsyncError> {
         puffin::profile_function!();
+        self.start_readback(device, queue).read(device)
+    }
+
+    /// Copies the texture's pixels back to the CPU.
+    ///
+    /// The copy is submitted right away. The returned future doesn't borrow
+    /// the painter, so it can be awaited after the renderer is unlocked.
+    #[cfg(target_arch = "wasm32")]
+    pub fn read_pixels(
+        &self,
+        device: &Device,
+        queue: &Queue,
+    ) -> impl std::future::Future<Output = Result<ColorImage, BufferAsyncError>> + 'static {
+        puffin::profile_function!();
+        self.start_readback(device, queue).read_async()
+    }
+
+    /// The number of workgroups needed to cover the texture.
+    pub fn workgroup_count(&self) -> [u32; 2] {
+        let [w, h] = self.size();
+        let [x, y, _] = self.shader.workgroup_size();
+        [(w + x - 1) / x, (h + y - 1) / y]
+    }
+
+    pub fn compute<'rp>(&'rp self, pass: &mut ComputePass<'rp>) {
+        let [x, y] = self.workgroup_count();
+        puffin::profile_function!(format!("{x}x{y} workgroups"));
         pass.set_bind_group(0, &self.bind_group, &[]);
         pass.set_pipeline(&self.pipeline);
-        let extent = self.texture.size();
-        pass.dispatch_workgroups(extent.width, extent.height, 1);
+        pass.dispatch_workgroups(x, y, 1);
     }
 
-    pub fn oneshot(&self, rc: &RenderState) {
-        let mut encoder = rc.device.create_command_encoder(&CommandEncoderDescriptor {
-            label: Some("simple_image oneshot commands"),
+    /// Updates the texture for `settings` if it isn't already up to date.
+    pub fn refresh(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        settings: &Settings,
+    ) {
+        if let Some(ms) = self.timer.as_mut().and_then(|timer| timer.poll(device)) {
+            let [x, y] = self.workgroup_count();
+            debug!("simple_image: compute pass took {ms:.3} ms ({x}x{y} workgroups)");
+        }
+        if self.computed.as_ref() != Some(settings) {
+            self.update(queue, settings);
+            match self.timer.take() {
+                Some(mut timer) => {
+                    timer.time(encoder, |encoder| self.dispatch(encoder));
+                    self.timer = Some(timer);
+                }
+                None => self.dispatch(encoder),
+            }
+            self.computed = Some(*settings);
+        }
+    }
+
+    /// Records the compute pass into `encoder`.
+    pub fn dispatch(&self, encoder: &mut CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("simple_image compute pass"),
         });
-        {
-            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
-                label: Some("simple_image oneshot compute pass begin"),
-            });
-            self.compute(&mut pass);
+        self.compute(&mut pass);
+    }
+}
+
+/// A painter along with what's needed to draw its output.
+struct Entry {
+    painter: Painter,
+    display: BindGroup,
+}
+
+/// The `simple_image` resources for all the widgets, stored in
+/// `egui_wgpu::CallbackResources`.
+///
+/// The blit pipeline used to draw the images is shared. Each widget gets its
+/// own [`Painter`] keyed by the widget's id.
+pub struct Painters {
+    blit: BlitPipeline,
+    entries: HashMap<u64, Entry>,
+}
+
+impl Painters {
+    pub fn new(device: &Device, target_format: TextureFormat) -> Self {
+        Self {
+            blit: BlitPipeline::new(device, target_format),
+            entries: HashMap::new(),
         }
-        rc.queue.submit([encoder.finish()].into_iter());
+    }
+
+    /// The painter for widget `id` running `shader` on a `width` x `height`
+    /// texture, allocated on first use and updated when the shader or size
+    /// changes.
+    pub fn get_or_insert(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        id: u64,
+        shader: &ComputeShader,
+        width: u32,
+        height: u32,
+    ) -> &mut Painter {
+        let entry = match self.entries.entry(id) {
+            Occupied(e) => {
+                let entry = e.into_mut();
+                entry.painter.set_shader(device, shader);
+                if entry.painter.size() != [width, height] {
+                    trace!("simple_image {id}: resizing texture to {width}x{height}");
+                    entry.painter.resize(device, width, height);
+                    entry.display = self.blit.bind(device, entry.painter.texture());
+                }
+                entry
+            }
+            Vacant(e) => {
+                let view_formats = self.blit.view_formats(Painter::FORMAT);
+                let painter = Painter::new(device, queue, shader, &view_formats, width, height);
+                let display = self.blit.bind(device, painter.texture());
+                e.insert(Entry { painter, display })
+            }
+        };
+        &mut entry.painter
+    }
+
+    /// The painter for widget `id`, if it has been painted.
+    pub fn get(&self, id: u64) -> Option<&Painter> {
+        self.entries.get(&id).map(|entry| &entry.painter)
+    }
+
+    pub fn paint<'rp>(&'rp self, id: u64, pass: &mut RenderPass<'rp>) {
+        if let Some(entry) = self.entries.get(&id) {
+            self.blit.paint(&entry.display, pass);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::widgets::gpu;
+
+    #[test]
+    fn rejected_shaders_keep_the_old_pipeline() {
+        let Some((device, queue)) = gpu::test_device() else {
+            return;
+        };
+        let good = ComputeShader::default();
+        let mut painter = Painter::new(&device, &queue, &good, &[], 16, 16);
+        painter.refresh(
+            &device,
+            &queue,
+            &mut device.create_command_encoder(&Default::default()),
+            &Settings::default(),
+        );
+
+        // Without the error scope this would be an uncaptured device error,
+        // which panics.
+        let bad = ComputeShader::unchecked("this isn't wgsl", [1, 1, 1]);
+        painter.set_shader(&device, &bad);
+        assert_eq!(painter.shader, good);
+        assert_eq!(painter.rejected, Some(bad));
+        assert!(painter.computed.is_some());
     }
 }
@@ -0,0 +1,270 @@
+//! Checks user supplied WGSL for the `simple_image` compute shader before it
+//! gets anywhere near the device.
+//!
+//! wgpu treats an invalid shader module or a pipeline whose bindings don't
+//! match the layout as a device error, which panics by default. So sources
+//! are validated with naga here, and only ones that pass are compiled.
+
+use std::{fmt, sync::Arc};
+
+use eframe::wgpu::Limits;
+use naga::{
+    front::wgsl,
+    valid::{Capabilities, ValidationFlags, Validator},
+    AddressSpace, ImageClass, ImageDimension, ResourceBinding, ShaderStage, StorageAccess,
+    StorageFormat, TypeInner,
+};
+
+/// The shader the widget starts out with.
+pub const DEFAULT_SOURCE: &str = include_str!("compute.wgsl");
+
+/// The compute entry point the pipeline is created with.
+pub const ENTRY_POINT: &str = "main";
+
+// Size of `painter::Settings`.
+const MAX_UNIFORM_SIZE: u32 = 16;
+
+/// WGSL source that is known to be compatible with the painter's bind group
+/// layout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComputeShader {
+    source: Arc<str>,
+    workgroup_size: [u32; 3],
+}
+
+impl ComputeShader {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The entry point's `@workgroup_size`.
+    pub fn workgroup_size(&self) -> [u32; 3] {
+        self.workgroup_size
+    }
+
+    /// Skips validation, to test what happens when the device rejects a
+    /// shader.
+    #[cfg(test)]
+    pub(crate) fn unchecked(source: &str, workgroup_size: [u32; 3]) -> Self {
+        Self {
+            source: source.into(),
+            workgroup_size,
+        }
+    }
+}
+
+impl Default for ComputeShader {
+    fn default() -> Self {
+        validate(DEFAULT_SOURCE).expect("the built-in compute shader should be valid")
+    }
+}
+
+/// Why a shader was rejected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderError {
+    /// 1-based line the error points at, if it points anywhere.
+    pub line: Option<u32>,
+    /// Human readable description. For parse and validation errors this
+    /// includes the offending source lines.
+    pub message: String,
+}
+
+impl ShaderError {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            line: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Parses and validates `source`, and checks that it fits the painter:
+///
+/// - a `@compute` entry point named `main`,
+/// - `@group(0) @binding(0)`: a `texture_storage_2d<rgba8unorm, write>`,
+/// - `@group(0) @binding(1)`: a uniform of at most 16 bytes,
+///
+/// and no other bindings.
+pub fn validate(source: &str) -> Result<ComputeShader, ShaderError> {
+    puffin::profile_function!();
+    let module = wgsl::parse_str(source).map_err(|e| ShaderError {
+        line: e.location(source).map(|l| l.line_number),
+        message: e.emit_to_string(source),
+    })?;
+    Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(&module)
+        .map_err(|e| ShaderError {
+            line: e.location(source).map(|l| l.line_number),
+            message: e.emit_to_string(source),
+        })?;
+
+    let entry_point = module
+        .entry_points
+        .iter()
+        .find(|ep| ep.stage == ShaderStage::Compute && ep.name == ENTRY_POINT)
+        .ok_or_else(|| ShaderError::new(format!("missing `@compute fn {ENTRY_POINT}`")))?;
+    let workgroup_size = entry_point.workgroup_size;
+    // The limits every device eframe creates supports.
+    let limits = Limits::default();
+    let max_size = [
+        limits.max_compute_workgroup_size_x,
+        limits.max_compute_workgroup_size_y,
+        limits.max_compute_workgroup_size_z,
+    ];
+    if let Some(((axis, size), max)) = "xyz"
+        .chars()
+        .zip(workgroup_size)
+        .zip(max_size)
+        .find(|&((_, size), max)| size > max)
+    {
+        return Err(ShaderError::new(format!(
+            "@workgroup_size{workgroup_size:?} is {size} along {axis}, more than {max}"
+        )));
+    }
+    let max_invocations = limits.max_compute_invocations_per_workgroup;
+    if workgroup_size.iter().product::<u32>() > max_invocations {
+        return Err(ShaderError::new(format!(
+            "@workgroup_size{workgroup_size:?} has more than {max_invocations} invocations"
+        )));
+    }
+
+    for (handle, var) in module.global_variables.iter() {
+        let Some(binding) = &var.binding else {
+            continue;
+        };
+        let name = var.name.as_deref().unwrap_or("<unnamed>");
+        let ty = &module.types[var.ty].inner;
+        let span = module.global_variables.get_span(handle);
+        let ok = match binding {
+            ResourceBinding {
+                group: 0,
+                binding: 0,
+            } => matches!(
+                (var.space, ty),
+                (
+                    AddressSpace::Handle,
+                    TypeInner::Image {
+                        dim: ImageDimension::D2,
+                        arrayed: false,
+                        class: ImageClass::Storage {
+                            format: StorageFormat::Rgba8Unorm,
+                            access: StorageAccess::STORE,
+                        },
+                    },
+                )
+            ),
+            ResourceBinding {
+                group: 0,
+                binding: 1,
+            } => var.space == AddressSpace::Uniform && ty.size(module.to_ctx()) <= MAX_UNIFORM_SIZE,
+            _ => false,
+        };
+        if !ok {
+            let expected = match (binding.group, binding.binding) {
+                (0, 0) => "a `texture_storage_2d<rgba8unorm, write>`".to_owned(),
+                (0, 1) => format!("a uniform of at most {MAX_UNIFORM_SIZE} bytes"),
+                _ => "nothing".to_owned(),
+            };
+            return Err(ShaderError {
+                line: span.is_defined().then(|| span.location(source).line_number),
+                message: format!(
+                    "`{name}` at @group({}) @binding({}) doesn't match the painter, \
+                     which expects {expected} there",
+                    binding.group, binding.binding
+                ),
+            });
+        }
+    }
+
+    Ok(ComputeShader {
+        source: source.into(),
+        workgroup_size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The default shader with `from` replaced by `to`.
+    fn with(from: &str, to: &str) -> String {
+        assert!(
+            DEFAULT_SOURCE.contains(from),
+            "{from:?} isn't in the default shader"
+        );
+        DEFAULT_SOURCE.replace(from, to)
+    }
+
+    #[test]
+    fn default_shader_is_valid() {
+        assert_eq!(ComputeShader::default().workgroup_size(), [8, 8, 1]);
+    }
+
+    #[test]
+    fn wrong_bindings_are_rejected() {
+        let wrong_format = with("rgba8unorm", "rgba16float");
+        let error = validate(&wrong_format).unwrap_err();
+        assert!(
+            error.message.contains("`out` at @group(0) @binding(0)"),
+            "{error}"
+        );
+        assert_eq!(error.line, Some(7));
+
+        // nothing left at binding 0, and something at binding 2
+        let moved = with("@binding(0)", "@binding(2)");
+        let error = validate(&moved).unwrap_err();
+        assert!(error.message.contains("expects nothing there"), "{error}");
+
+        let not_uniform = with("var<uniform> settings", "var<storage> settings");
+        let error = validate(&not_uniform).unwrap_err();
+        assert!(error.message.contains("@binding(1)"), "{error}");
+
+        let too_big = with("time: f32", "time: f32,\n    more: array<vec4<f32>, 2>");
+        let error = validate(&too_big).unwrap_err();
+        assert!(error.message.contains("at most 16 bytes"), "{error}");
+    }
+
+    #[test]
+    fn wrong_entry_point_is_rejected() {
+        let error = validate(&with("fn main(", "fn run(")).unwrap_err();
+        assert!(
+            error.message.contains("missing `@compute fn main`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn workgroups_over_the_default_limits_are_rejected() {
+        // 64 along z is the most every device supports
+        let ok = validate(&with("@workgroup_size(8, 8)", "@workgroup_size(1, 1, 64)")).unwrap();
+        assert_eq!(ok.workgroup_size(), [1, 1, 64]);
+        let error =
+            validate(&with("@workgroup_size(8, 8)", "@workgroup_size(1, 1, 128)")).unwrap_err();
+        assert!(error.message.contains("128 along z"), "{error}");
+
+        // each axis fits but the total doesn't
+        let error =
+            validate(&with("@workgroup_size(8, 8)", "@workgroup_size(16, 32)")).unwrap_err();
+        assert!(
+            error.message.contains("more than 256 invocations"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn parse_errors_point_at_their_line() {
+        let error = validate(&with("let p =", "let p = = ")).unwrap_err();
+        assert_eq!(error.line, Some(23));
+        assert!(error.message.contains("let p = = "), "{error}");
+        assert!(error.to_string().starts_with("line 23: "), "{error}");
+    }
+}
@@ -0,0 +1,138 @@
+//! Measures how long GPU work takes with timestamp queries.
+
+use std::sync::{Arc, Mutex};
+
+use eframe::egui_wgpu::wgpu;
+use log::trace;
+use wgpu::{
+    Buffer, BufferAsyncError, BufferDescriptor, BufferUsages, CommandEncoder, Device, Features,
+    Maintain, MapMode, QuerySet, QuerySetDescriptor, QueryType, Queue,
+};
+
+// Two u64 timestamps
+const SIZE: u64 = 2 * std::mem::size_of::<u64>() as u64;
+
+#[derive(Debug)]
+enum State {
+    Idle,
+    // Timestamps were recorded into a command encoder that hasn't been
+    // submitted yet.
+    Recorded,
+    // Waiting on the readback buffer to be mapped.
+    Mapping(Arc<Mutex<Option<Result<(), BufferAsyncError>>>>),
+}
+
+/// Times the commands recorded between two timestamps.
+///
+/// The result is read back asynchronously, a few frames later, so only one
+/// measurement is in flight at a time.
+pub struct GpuTimer {
+    query_set: QuerySet,
+    resolve: Buffer,
+    readback: Buffer,
+    period_ns: f32,
+    state: State,
+}
+
+// QuerySet isn't Debug
+impl std::fmt::Debug for GpuTimer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GpuTimer")
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GpuTimer {
+    /// Returns `None` if the device wasn't created with
+    /// [`Features::TIMESTAMP_QUERY`].
+    pub fn new(device: &Device, queue: &Queue) -> Option<Self> {
+        if !device.features().contains(Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            label: Some("gpu timer queries"),
+            ty: QueryType::Timestamp,
+            count: 2,
+        });
+        let resolve = device.create_buffer(&BufferDescriptor {
+            label: Some("gpu timer resolve buffer"),
+            size: SIZE,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&BufferDescriptor {
+            label: Some("gpu timer readback buffer"),
+            size: SIZE,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve,
+            readback,
+            period_ns: queue.get_timestamp_period(),
+            state: State::Idle,
+        })
+    }
+
+    /// Brackets the commands `record` adds to `encoder` with timestamps,
+    /// unless a previous measurement is still in flight.
+    pub fn time(&mut self, encoder: &mut CommandEncoder, record: impl FnOnce(&mut CommandEncoder)) {
+        if !matches!(self.state, State::Idle) {
+            record(encoder);
+            return;
+        }
+        encoder.write_timestamp(&self.query_set, 0);
+        record(encoder);
+        encoder.write_timestamp(&self.query_set, 1);
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve, 0, &self.readback, 0, SIZE);
+        self.state = State::Recorded;
+    }
+
+    /// Advances the readback of the last measurement, returning it in
+    /// milliseconds once it's available.
+    ///
+    /// Call once per frame, before recording. The commands recorded in the
+    /// previous frame must have been submitted.
+    pub fn poll(&mut self, device: &Device) -> Option<f64> {
+        match &self.state {
+            State::Idle => None,
+            State::Recorded => {
+                let result = Arc::new(Mutex::new(None));
+                let sender = result.clone();
+                self.readback
+                    .slice(..)
+                    .map_async(MapMode::Read, move |r| *sender.lock().unwrap() = Some(r));
+                self.state = State::Mapping(result);
+                None
+            }
+            State::Mapping(result) => {
+                device.poll(Maintain::Poll);
+                let result = result.lock().unwrap().take();
+                match result {
+                    None => None,
+                    Some(Ok(())) => {
+                        let ms = {
+                            let bytes = self.readback.slice(..).get_mapped_range();
+                            let [start, end] = [0, 1].map(|i| {
+                                let b = &bytes[8 * i..8 * (i + 1)];
+                                u64::from_le_bytes(b.try_into().unwrap())
+                            });
+                            end.wrapping_sub(start) as f64 * self.period_ns as f64 * 1e-6
+                        };
+                        self.readback.unmap();
+                        self.state = State::Idle;
+                        Some(ms)
+                    }
+                    Some(Err(e)) => {
+                        trace!("gpu timer: readback failed: {e}");
+                        self.state = State::Idle;
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
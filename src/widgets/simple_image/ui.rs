@@ -1,55 +1,445 @@
-use eframe::CreationContext;
-use egui::{load::SizedTexture, Image, ImageSource, Sense, Vec2, Widget};
-use log::info;
+use std::{ops::Range, time::Duration};
+
+use eframe::egui_wgpu::{self, RenderState};
+use egui::{
+    pos2, text::LayoutJob, Color32, Id, RichText, Sense, TextureHandle, TextureOptions, Vec2,
+    Widget,
+};
+use egui_extras::syntax_highlighting::{self, CodeTheme};
 use serde::{Deserialize, Serialize};
 
-use super::painter::Settings;
+use crate::widgets::{
+    gpu::{self, next_id},
+    player::{self, PlayerState},
+    png,
+};
+
+use super::{
+    fallback,
+    painter::{Painters, Settings},
+    shader::{self, ComputeShader, ShaderError},
+};
+
+// height / width
+const ASPECT_RATIO: f32 = 0.75;
+
+// How long the widget's size has to stay put before the texture is
+// reallocated to match.
+const RESIZE_DEBOUNCE_SECONDS: f64 = 0.25;
+
+/// The texture size in pixels, following the widget's size once it settles.
+#[derive(Debug, Default)]
+struct TextureSize {
+    current: Option<[u32; 2]>,
+    // the new size and when it was first seen
+    pending: Option<([u32; 2], f64)>,
+}
+
+impl TextureSize {
+    fn update(&mut self, ctx: &egui::Context, rect: egui::Rect) -> [u32; 2] {
+        let px = (rect.size() * ctx.pixels_per_point()).round();
+        let wanted = [px.x.max(1.0) as u32, px.y.max(1.0) as u32];
+        let now = ctx.input(|i| i.time);
+        match (self.current, self.pending) {
+            (None, _) => self.current = Some(wanted),
+            (Some(current), _) if current == wanted => self.pending = None,
+            (_, Some((pending, since))) if pending == wanted => {
+                if now - since >= RESIZE_DEBOUNCE_SECONDS {
+                    self.current = Some(wanted);
+                    self.pending = None;
+                } else {
+                    ctx.request_repaint_after(Duration::from_secs_f64(
+                        RESIZE_DEBOUNCE_SECONDS - (now - since),
+                    ));
+                }
+            }
+            _ => {
+                self.pending = Some((wanted, now));
+                ctx.request_repaint_after(Duration::from_secs_f64(RESIZE_DEBOUNCE_SECONDS));
+            }
+        }
+        self.current.unwrap_or(wanted)
+    }
+}
+
+/// The compute shader's source as edited in the app, and the last version of
+/// it that compiled.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+struct ShaderEditor {
+    source: String,
+
+    // `None` until `source` has been checked.
+    #[serde(skip)]
+    last_good: Option<ComputeShader>,
+    // Why `source` was rejected, if it was.
+    #[serde(skip)]
+    error: Option<ShaderError>,
+}
+
+impl Default for ShaderEditor {
+    fn default() -> Self {
+        Self {
+            source: shader::DEFAULT_SOURCE.to_owned(),
+            last_good: None,
+            error: None,
+        }
+    }
+}
+
+impl ShaderEditor {
+    /// The shader to run: the current source if it's valid, otherwise the
+    /// last one that was.
+    fn shader(&mut self) -> &ComputeShader {
+        if self.last_good.is_none() {
+            self.check();
+        }
+        self.last_good.get_or_insert_with(Default::default)
+    }
+
+    fn check(&mut self) {
+        match shader::validate(&self.source) {
+            Ok(shader) => {
+                self.last_good = Some(shader);
+                self.error = None;
+            }
+            Err(error) => {
+                log::debug!("simple_image: rejected shader: {error}");
+                self.error = Some(error);
+            }
+        }
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Compute shader")
+            .id_source(ui.id().with("shader"))
+            .show(ui, |ui| {
+                if !gpu::is_available(ui.ctx()) {
+                    ui.label("Edits take effect once the GPU renderer is available.");
+                }
+                let theme = CodeTheme::from_memory(ui.ctx());
+                let error_line = self.error.as_ref().and_then(|e| e.line);
+                let highlight = ui.visuals().error_fg_color.gamma_multiply(0.25);
+                let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+                    let mut job = syntax_highlighting::highlight(ui.ctx(), &theme, text, "rs");
+                    if let Some(range) = error_line.and_then(|line| line_range(text, line)) {
+                        set_background(&mut job, range, highlight);
+                    }
+                    job.wrap.max_width = wrap_width;
+                    ui.fonts(|f| f.layout_job(job))
+                };
+                let response = ui.add(
+                    egui::TextEdit::multiline(&mut self.source)
+                        .code_editor()
+                        .desired_rows(12)
+                        .desired_width(f32::INFINITY)
+                        .layouter(&mut layouter),
+                );
+                let mut changed = response.changed();
+                ui.horizontal(|ui| {
+                    if ui.button("Reset").clicked() {
+                        self.source = shader::DEFAULT_SOURCE.to_owned();
+                        changed = true;
+                    }
+                    match &self.error {
+                        Some(_) => ui.label("Running the last version that compiled."),
+                        None => ui.label("Compiled."),
+                    };
+                });
+                if changed {
+                    self.check();
+                }
+                if let Some(error) = &self.error {
+                    if let Some(line) = error.line {
+                        ui.colored_label(ui.visuals().error_fg_color, format!("line {line}:"));
+                    }
+                    ui.label(
+                        RichText::new(&error.message)
+                            .monospace()
+                            .color(ui.visuals().error_fg_color),
+                    );
+                }
+            });
+    }
+}
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+// Saves `image` and describes how that went.
+fn save(image: &egui::ColorImage, path: &str) -> String {
+    #[cfg(not(target_arch = "wasm32"))]
+    let result = png::save(image, path).map_err(|e| e.to_string());
+    #[cfg(target_arch = "wasm32")]
+    let result = png::download(image, path);
+    match result {
+        Ok(()) => format!("Saved {path}"),
+        Err(e) => {
+            log::error!("simple_image: saving {path} failed: {e}");
+            format!("Saving failed: {e}")
+        }
+    }
+}
+
+// Byte range of 1-based `line` in `text`, including its newline.
+fn line_range(text: &str, line: u32) -> Option<Range<usize>> {
+    let mut start = 0;
+    for (i, content) in text.split_inclusive('\n').enumerate() {
+        if i + 1 == line as usize {
+            return Some(start..start + content.len());
+        }
+        start += content.len();
+    }
+    None
+}
+
+// Sets the background of the text in `range`, splitting sections as needed.
+fn set_background(job: &mut LayoutJob, range: Range<usize>, color: Color32) {
+    let sections = std::mem::take(&mut job.sections);
+    for section in sections {
+        let r = section.byte_range.clone();
+        let cuts = [
+            r.start,
+            range.start.clamp(r.start, r.end),
+            range.end.clamp(r.start, r.end),
+            r.end,
+        ];
+        for w in cuts.windows(2).filter(|w| w[0] < w[1]) {
+            let mut piece = section.clone();
+            if w[0] != r.start {
+                piece.leading_space = 0.0;
+            }
+            piece.byte_range = w[0]..w[1];
+            if range.contains(&w[0]) {
+                piece.format.background = color;
+            }
+            job.sections.push(piece);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
 pub struct SimpleImage {
     pub style: Settings,
+    player: PlayerState,
+    shader: ShaderEditor,
+    // Where "Save image…" writes to. On the web, the download's file name.
+    save_path: String,
 
-    #[serde(skip)]
-    target: Option<SizedTexture>,
+    // keys this widget's painter in the callback resources
+    #[serde(skip, default = "next_id")]
+    id: u64,
 
     #[serde(skip)]
-    painter: Option<super::painter::Painter>,
+    size: TextureSize,
+    // Set by the "Save image…" button, handled by `save_if_requested`.
+    #[serde(skip)]
+    save_requested: bool,
+    // How the last save went.
+    #[serde(skip)]
+    save_status: Option<String>,
+}
+
+impl Default for SimpleImage {
+    fn default() -> Self {
+        Self {
+            style: Default::default(),
+            player: Default::default(),
+            shader: Default::default(),
+            save_path: "simple_image.png".to_owned(),
+            id: next_id(),
+            size: Default::default(),
+            save_requested: false,
+            save_status: None,
+        }
+    }
 }
 
 impl SimpleImage {
-    pub fn setup_renderer<'s>(&mut self, cc: &'s CreationContext<'s>) {
-        let rc = cc.wgpu_render_state.clone().unwrap();
-        let painter = super::painter::Painter::new(&rc, 640, 480).unwrap();
-
-        self.target = {
-            let tid = rc.renderer.write().register_native_texture(
-                &rc.device,
-                &painter.create_texture_view(),
-                eframe::wgpu::FilterMode::Nearest,
-            );
-            Some(SizedTexture::from((tid, Vec2::new(640.0, 480.0))))
-        };
+    /// Replaces the compute shader's source, e.g. with a version edited on
+    /// disk. A source that doesn't compile is logged and shown in the editor
+    /// while the last good shader keeps running.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_shader_source(&mut self, source: String) {
+        if self.shader.source == source {
+            return;
+        }
+        self.shader.source = source;
+        self.shader.check();
+        match &self.shader.error {
+            Some(e) => log::error!("simple_image: shader failed to compile: {e}"),
+            None => log::info!("simple_image: reloaded shader"),
+        }
+    }
 
-        painter.update(&rc.queue, &self.style);
-        painter.oneshot(&rc);
+    /// Saves the image as a PNG if "Save image…" was clicked.
+    ///
+    /// Call after showing the widget, with `frame.wgpu_render_state()`. On
+    /// native this blocks until the image has been read back from the GPU.
+    /// On the web the image is downloaded once it's ready.
+    pub fn save_if_requested(&mut self, render_state: Option<&RenderState>) {
+        if !std::mem::take(&mut self.save_requested) {
+            return;
+        }
+        let Some([width, height]) = self.size.current else {
+            return;
+        };
+        let Some(rc) = render_state else {
+            let image = fallback::render(&self.style, width, height);
+            self.save_status = Some(save(&image, &self.save_path));
+            return;
+        };
+        let renderer = rc.renderer.read();
+        let Some(painter) = renderer
+            .callback_resources
+            .get::<Painters>()
+            .and_then(|painters| painters.get(self.id))
+        else {
+            self.save_status = Some("Nothing to save yet.".to_owned());
+            return;
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.save_status = Some(match painter.read_pixels(&rc.device, &rc.queue) {
+                Ok(image) => save(&image, &self.save_path),
+                Err(e) => format!("Reading the image failed: {e}"),
+            });
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            let pixels = painter.read_pixels(&rc.device, &rc.queue);
+            let file_name = self.save_path.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match pixels.await {
+                    Ok(image) => log::info!("{}", save(&image, &file_name)),
+                    Err(e) => log::error!("simple_image: reading the image failed: {e}"),
+                }
+            });
+            self.save_status = Some(format!("Downloading {}", self.save_path));
+        }
+    }
 
-        self.painter = Some(painter);
-        // rc.renderer.write().callback_resources.insert(painter);
+    fn paint_fallback(&self, ui: &egui::Ui, rect: egui::Rect, [width, height]: [u32; 2]) {
+        // The texture handle is kept in egui's memory since it isn't
+        // serializable.
+        let id = Id::new(("simple_image fallback", self.id));
+        let cached = ui.data(|d| d.get_temp::<(Settings, TextureHandle)>(id));
+        let texture = match cached {
+            Some((settings, texture))
+                if settings == self.style && texture.size() == [width as _, height as _] =>
+            {
+                texture
+            }
+            cached => {
+                let image = fallback::render(&self.style, width, height);
+                let texture = match cached {
+                    Some((_, mut texture)) => {
+                        texture.set(image, TextureOptions::NEAREST);
+                        texture
+                    }
+                    None => ui.ctx().load_texture(
+                        "simple_image fallback",
+                        image,
+                        TextureOptions::NEAREST,
+                    ),
+                };
+                ui.data_mut(|d| d.insert_temp(id, (self.style, texture.clone())));
+                texture
+            }
+        };
+        let painter = ui.painter_at(rect);
+        painter.image(
+            texture.id(),
+            rect,
+            egui::Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
+            Color32::WHITE,
+        );
+        gpu::paint_unavailable_notice(&painter, rect);
     }
 }
 
 impl Widget for &mut SimpleImage {
     fn ui(self, ui: &mut egui::Ui) -> egui::Response {
         ui.label("before simple image");
-        let r = if let Some(target) = self.target {
-            info!("HERE");
-            ui.add(Image::new(ImageSource::Texture(target)))
+        let w = ui.available_width();
+        let (rect, response) = ui.allocate_exact_size(
+            Vec2::new(w, ASPECT_RATIO * w),
+            Sense::focusable_noninteractive(),
+        );
+        let size = self.size.update(ui.ctx(), rect);
+        // Paint after the controller has updated the time, so seeking while
+        // paused shows the new frame right away.
+        ui.add(player::Controller::new(
+            ("simple_image player", self.id),
+            &mut self.player,
+            &mut self.style.time,
+        ));
+        if gpu::is_available(ui.ctx()) {
+            ui.painter().add(egui_wgpu::Callback::new_paint_callback(
+                rect,
+                SimpleImageCallback {
+                    id: self.id,
+                    style: self.style,
+                    shader: self.shader.shader().clone(),
+                    size,
+                },
+            ));
         } else {
-            let (_, response) =
-                ui.allocate_exact_size(Vec2::new(640.0, 480.0), Sense::focusable_noninteractive());
-            response
-        };
+            self.paint_fallback(ui, rect, size);
+        }
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.save_path).desired_width(160.0));
+            if ui.button("Save image…").clicked() {
+                self.save_requested = true;
+            }
+            if let Some(status) = &self.save_status {
+                ui.label(status);
+            }
+        });
+        self.shader.ui(ui);
         ui.label("after simple image");
-        r
+        response
+    }
+}
+
+// What the paint callback needs to know about the widget.
+struct SimpleImageCallback {
+    id: u64,
+    style: Settings,
+    shader: ComputeShader,
+    // texture size in pixels
+    size: [u32; 2],
+}
+
+impl egui_wgpu::CallbackTrait for SimpleImageCallback {
+    fn paint<'a>(
+        &'a self,
+        _info: egui::PaintCallbackInfo,
+        render_pass: &mut eframe::wgpu::RenderPass<'a>,
+        callback_resources: &'a egui_wgpu::CallbackResources,
+    ) {
+        puffin::profile_function!();
+        if let Some(painters) = callback_resources.get::<Painters>() {
+            painters.paint(self.id, render_pass);
+        }
+    }
+
+    fn prepare(
+        &self,
+        device: &eframe::wgpu::Device,
+        queue: &eframe::wgpu::Queue,
+        egui_encoder: &mut eframe::wgpu::CommandEncoder,
+        callback_resources: &mut egui_wgpu::CallbackResources,
+    ) -> Vec<eframe::wgpu::CommandBuffer> {
+        puffin::profile_function!();
+        let max_size = device.limits().max_texture_dimension_2d;
+        let [width, height] = self.size.map(|e| e.min(max_size));
+        let Some(target_format) = gpu::target_format(callback_resources) else {
+            return Vec::new();
+        };
+        callback_resources
+            .entry::<Painters>()
+            .or_insert_with(|| Painters::new(device, target_format))
+            .get_or_insert(device, queue, self.id, &self.shader, width, height)
+            .refresh(device, queue, egui_encoder, &self.style);
+        Vec::new()
     }
 }
@@ -0,0 +1,117 @@
+//! Rendering [`WavyRectangles`] to an image without a window.
+
+use std::path::Path;
+
+use eframe::wgpu::{
+    self, Color, CommandEncoderDescriptor, Device, LoadOp, Operations, Queue,
+    RenderPassColorAttachment, RenderPassDescriptor, TextureDescriptor, TextureFormat,
+    TextureUsages,
+};
+use egui::ColorImage;
+
+pub use crate::widgets::export::ExportError;
+use crate::widgets::{export, gpu, png, readback::Readback};
+
+use super::{
+    painter::{RectPainter, RectPipeline, Viewport},
+    shading,
+    ui::WavyRectangles,
+};
+
+// Matches the non-sRGB formats egui renders into, so exports look like the
+// widget does on screen.
+const FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+/// Renders `rects` into a `width` x `height` image on a transparent
+/// background.
+///
+/// The rects are laid out to fill the image, as they fill the widget, at one
+/// pixel per point. Fails if either side is 0 or larger than `device`
+/// supports.
+pub fn render(
+    device: &Device,
+    queue: &Queue,
+    rects: &WavyRectangles,
+    width: u32,
+    height: u32,
+) -> Result<ColorImage, ExportError> {
+    puffin::profile_function!();
+    export::check_size(device, width, height)?;
+    let pipeline = RectPipeline::new(device, FORMAT);
+    let mut painter = RectPainter::new(device, &pipeline);
+    let viewport = viewport(width, height);
+    painter.set_rects(device, queue, &rects.rects(viewport.size()));
+    painter.set_uniforms(queue, &rects.style, viewport);
+
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("wavy_rects export texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&Default::default());
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("wavy_rects export encoder"),
+    });
+    {
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("wavy_rects export pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        painter.paint(&pipeline, &mut pass);
+    }
+    // The painter's output has premultiplied alpha.
+    let readback = Readback::new(device, &mut encoder, &texture, true);
+    queue.submit(Some(encoder.finish()));
+    readback.read(device).map_err(ExportError::Readback)
+}
+
+/// Renders `rects` like [`render`] but on the CPU, with [`shading::rasterize`].
+///
+/// Slow, but doesn't need a GPU, so it's useful as a reference.
+pub fn render_cpu(rects: &WavyRectangles, width: u32, height: u32) -> ColorImage {
+    let viewport = viewport(width, height);
+    shading::rasterize(&rects.rects(viewport.size()), &rects.style, &viewport)
+}
+
+fn viewport(width: u32, height: u32) -> Viewport {
+    Viewport {
+        size_px: [width as f32, height as f32],
+        pixels_per_point: 1.0,
+    }
+}
+
+/// Renders `rects` with [`render`] on a [headless device](gpu::headless_device)
+/// and saves the result as a PNG at `path`.
+pub fn save_png(
+    rects: &WavyRectangles,
+    width: u32,
+    height: u32,
+    path: impl AsRef<Path>,
+) -> Result<(), ExportError> {
+    let (device, queue) = gpu::headless_device().ok_or(ExportError::NoDevice)?;
+    let image = render(&device, &queue, rects, width, height)?;
+    write_png(&image, path)
+}
+
+/// Saves `image` as a PNG with straight (not premultiplied) alpha.
+pub fn write_png(image: &ColorImage, path: impl AsRef<Path>) -> Result<(), ExportError> {
+    png::save(image, path).map_err(ExportError::Image)
+}
@@ -0,0 +1,88 @@
+//! Draws the rects with egui shapes for when wgpu isn't available.
+
+use std::f32::consts::FRAC_PI_2;
+
+use egui::{pos2, vec2, Color32, Painter, Pos2, Shape, Stroke, Vec2};
+
+use super::{
+    painter::{Rect, RectPainterSettings},
+    shading,
+};
+
+// segments per rounded corner
+const CORNER_SEGMENTS: usize = 8;
+
+/// Paints `rects` into `target`.
+///
+/// The rects are in points relative to the top left of `target`, as for the
+/// GPU path.
+pub fn paint(
+    painter: &Painter,
+    target: egui::Rect,
+    rects: &[Rect],
+    defaults: &RectPainterSettings,
+) {
+    puffin::profile_function!();
+    let pixels_per_point = painter.ctx().pixels_per_point();
+    let shapes = rects.iter().map(|r| {
+        let style = r.style.resolve(defaults);
+        let style_px = style.to_px(pixels_per_point);
+        let center = target.min + Vec2::from(r.center);
+        let half_size = 0.5 * Vec2::from(r.size);
+
+        // The stroke is centered on the outline, but the shader draws the
+        // edge inside the rect, so inset the outline by half a line width.
+        let line_width = style.line_width.max(0.0);
+        let inset = 0.5 * line_width.min(half_size.min_elem());
+        let radius = style.corner_radius.max(0.0);
+        let outline = rounded_rect(
+            half_size - Vec2::splat(inset),
+            (radius - inset).max(0.0),
+            r.orientation_radians,
+        );
+
+        Shape::convex_polygon(
+            outline.into_iter().map(|p| center + p).collect(),
+            color(style.fill),
+            if style_px.line_width < shading::MIN_LINE_WIDTH_PX {
+                Stroke::NONE
+            } else {
+                Stroke::new(line_width, color(shading::edge_color(&style_px)))
+            },
+        )
+    });
+    painter.extend(shapes);
+}
+
+fn color(rgba: [f32; 4]) -> Color32 {
+    let [r, g, b, a] =
+        shading::premultiply(rgba.map(|v| v.clamp(0.0, 1.0))).map(|v| (255.0 * v).round() as u8);
+    Color32::from_rgba_premultiplied(r, g, b, a)
+}
+
+// Outline of a rounded rect centered on the origin, rotated by `theta`.
+fn rounded_rect(half_size: Vec2, radius: f32, theta: f32) -> Vec<Vec2> {
+    let radius = radius.min(half_size.min_elem()).max(0.0);
+    let inner = half_size - Vec2::splat(radius);
+    let (s, c) = theta.sin_cos();
+    let corners = [
+        (vec2(1.0, 1.0), 0.0),
+        (vec2(-1.0, 1.0), FRAC_PI_2),
+        (vec2(-1.0, -1.0), 2.0 * FRAC_PI_2),
+        (vec2(1.0, -1.0), 3.0 * FRAC_PI_2),
+    ];
+    corners
+        .into_iter()
+        .flat_map(|(sign, start)| {
+            (0..=CORNER_SEGMENTS).map(move |i| {
+                let a = start + FRAC_PI_2 * i as f32 / CORNER_SEGMENTS as f32;
+                sign * inner + radius * vec2(a.cos(), a.sin())
+            })
+        })
+        .map(|p| rotate(pos2(p.x, p.y), s, c).to_vec2())
+        .collect()
+}
+
+fn rotate(p: Pos2, s: f32, c: f32) -> Pos2 {
+    pos2(p.x * c - p.y * s, p.x * s + p.y * c)
+}
@@ -0,0 +1,429 @@
+//! Where the rects go and the triangles they're drawn with.
+
+use std::{
+    f32::consts::{PI, SQRT_2},
+    ops::RangeInclusive,
+};
+
+use egui::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+use super::painter::{Rect, RectStyle, Viewport};
+
+/// Each rect is drawn as one triangle, generated in the vertex shader.
+pub const VERTICES_PER_RECT: u32 = 3;
+
+/// How far each side of a triangle is from the rect it encloses, so the edges
+/// can be anti-aliased. Matches `AA_PADDING_PX` in `painter.wgsl`.
+pub const AA_PADDING_PX: f32 = 1.0;
+
+/// The shape of the wave, as a function of phase in turns. Every shape
+/// repeats each turn and stays within `0..=1`. All but the sawtooth peak on
+/// whole turns and bottom out half way between.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Easing {
+    #[default]
+    Cosine,
+    Triangle,
+    /// A triangle wave smoothed at the peaks, so rects linger there.
+    Smoothstep,
+    /// Rises linearly over each turn, then drops back.
+    Sawtooth,
+}
+
+impl Easing {
+    pub const ALL: [Self; 4] = [
+        Self::Cosine,
+        Self::Triangle,
+        Self::Smoothstep,
+        Self::Sawtooth,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cosine => "cosine",
+            Self::Triangle => "triangle",
+            Self::Smoothstep => "smoothstep",
+            Self::Sawtooth => "sawtooth",
+        }
+    }
+
+    /// The wave's height at `turns`, in `0..=1`.
+    pub fn apply(&self, turns: f32) -> f32 {
+        let u = turns.rem_euclid(1.0);
+        let triangle = (2.0 * u - 1.0).abs();
+        match self {
+            Self::Cosine => 0.5 * (1.0 + (2.0 * PI * turns).cos()),
+            Self::Triangle => triangle,
+            Self::Smoothstep => triangle * triangle * (3.0 - 2.0 * triangle),
+            Self::Sawtooth => u,
+        }
+    }
+}
+
+/// How the rects move.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct WaveParams {
+    /// Time for the wave to travel its length once.
+    pub period_seconds: f32,
+    /// Height of the wave as a fraction of the bounds.
+    pub amplitude: f32,
+    /// Number of wave lengths across the row of rects.
+    pub phase_spread: f32,
+    pub easing: Easing,
+    /// Every other rect turns the other way when set.
+    pub alternate_rotation: bool,
+    pub rotation_degrees_per_second: f32,
+    /// Rect size as a multiple of the spacing between rects.
+    pub size_scale: f32,
+    /// How much the rects grow and shrink, as a fraction of their size. The
+    /// width and height pulse a quarter period apart.
+    pub pulse_amplitude: f32,
+    pub pulse_period_seconds: f32,
+}
+
+impl Default for WaveParams {
+    fn default() -> Self {
+        Self {
+            period_seconds: 7.0,
+            amplitude: 1.0,
+            phase_spread: 1.0,
+            easing: Easing::Cosine,
+            alternate_rotation: true,
+            rotation_degrees_per_second: 360.0 / 7.0,
+            size_scale: 2.5,
+            pulse_amplitude: 0.0,
+            pulse_period_seconds: 2.0,
+        }
+    }
+}
+
+impl WaveParams {
+    pub const PERIOD_RANGE: RangeInclusive<f32> = 0.5..=30.0;
+    pub const SIZE_SCALE_RANGE: RangeInclusive<f32> = 0.1..=10.0;
+    pub const PULSE_AMPLITUDE_RANGE: RangeInclusive<f32> = 0.0..=0.9;
+    pub const PULSE_PERIOD_RANGE: RangeInclusive<f32> = 0.1..=10.0;
+
+    /// A copy with the fields that could break the layout, e.g. a period of
+    /// 0, clamped to the ranges the UI allows. Persisted values aren't
+    /// checked when they're loaded.
+    pub fn clamped(&self) -> Self {
+        fn clamp(x: f32, range: RangeInclusive<f32>) -> f32 {
+            let (min, max) = range.into_inner();
+            x.clamp(min, max)
+        }
+        Self {
+            period_seconds: clamp(self.period_seconds, Self::PERIOD_RANGE),
+            amplitude: self.amplitude.clamp(0.0, 1.0),
+            size_scale: clamp(self.size_scale, Self::SIZE_SCALE_RANGE),
+            pulse_amplitude: clamp(self.pulse_amplitude, Self::PULSE_AMPLITUDE_RANGE),
+            pulse_period_seconds: clamp(self.pulse_period_seconds, Self::PULSE_PERIOD_RANGE),
+            ..*self
+        }
+    }
+}
+
+/// `steps` rects spread over `x0..x1` and `y0..y1`, moving as `wave`
+/// describes at `time_seconds`. The result is in the same units as the
+/// bounds.
+///
+/// Sizes are `wave.size_scale` times the spacing between rects, but at least
+/// `size_scale` eighteenths of the width. They shrink if needed so that the
+/// rects stay within the bounds at any rotation and pulse.
+pub fn make_rects(
+    time_seconds: f32,
+    wave: &WaveParams,
+    steps: u32,
+    x0: f32,
+    x1: f32,
+    y0: f32,
+    y1: f32,
+) -> Vec<Rect> {
+    puffin::profile_function!();
+    let wave = wave.clamped();
+    let (width, height) = (x1 - x0, y1 - y0);
+    let growth = 1.0 + wave.pulse_amplitude;
+    // The farthest a corner gets from its center is half the diagonal of the
+    // rect at its biggest.
+    let sz = (wave.size_scale * (width / (steps + 1) as f32).max(width / 18.0))
+        .min(width.min(height) / (SQRT_2 * growth));
+    let reach = 0.5 * SQRT_2 * growth * sz;
+    let (x0, x1, y0, y1) = (x0 + reach, x1 - reach, y0 + reach, y1 - reach);
+    let dx = (x1 - x0) / (steps + 1) as f32;
+    let dy = y1 - y0;
+    let amplitude = wave.amplitude;
+    let travel = time_seconds / wave.period_seconds;
+    let pulse = 2.0 * PI * time_seconds / wave.pulse_period_seconds;
+    let th = (time_seconds * wave.rotation_degrees_per_second).to_radians();
+    (0..steps)
+        .map(|i| {
+            let reverse = wave.alternate_rotation && (i & 1) == 1;
+            let i = i as f32;
+            let ph = wave.phase_spread * i / (steps + 1) as f32;
+            let cx = x0 + dx * (i + 0.5);
+            // y is down, so measure the wave up from the bottom
+            let height = 0.5 + amplitude * (wave.easing.apply(ph + travel) - 0.5);
+            let cy = y1 - dy * height;
+            let w = sz * (1.0 + wave.pulse_amplitude * (pulse + 2.0 * PI * ph).cos());
+            let h = sz * (1.0 + wave.pulse_amplitude * (pulse + 2.0 * PI * ph).sin());
+            Rect {
+                center: [cx, cy],
+                size: [w.max(0.0), h.max(0.0)],
+                orientation_radians: if reverse { -th } else { th },
+                style: RectStyle::INHERIT,
+            }
+        })
+        .collect()
+}
+
+/// The corners of the triangle the vertex shader draws for `rect` in
+/// `viewport`, in clip space.
+///
+/// The right angle sits on one corner of the rect, the legs run along two of
+/// its sides and the hypotenuse touches the opposite corner. Then each side
+/// is moved out by [`AA_PADDING_PX`].
+pub fn enclosing_triangle(rect: &Rect, viewport: &Viewport) -> [Vec2; 3] {
+    let center = Vec2::from(rect.center) * viewport.pixels_per_point;
+    let half_size = 0.5 * Vec2::from(rect.size) * viewport.pixels_per_point;
+    let side = half_size.x + half_size.y;
+    let (s, c) = rect.orientation_radians.sin_cos();
+    // clockwise in pixels, which are y down, so counter-clockwise in clip space
+    let v = [vec2(0.0, 0.0), vec2(0.0, 2.0), vec2(2.0, 0.0)].map(|corner| {
+        let p = corner * side - half_size;
+        vec2(p.x * c - p.y * s, p.x * s + p.y * c) + center
+    });
+
+    // Scaling about the incenter moves every side out by the same distance.
+    let a = (v[1] - v[2]).length();
+    let b = (v[2] - v[0]).length();
+    let e = (v[0] - v[1]).length();
+    let perimeter = a + b + e;
+    let incenter = (a * v[0] + b * v[1] + e * v[2]) / perimeter;
+    let (u, w) = (v[1] - v[0], v[2] - v[0]);
+    let inradius = (u.x * w.y - u.y * w.x).abs() / perimeter;
+    let k = 1.0 + AA_PADDING_PX / inradius.max(1e-6);
+    v.map(|v| viewport.px_to_clip(incenter + (v - incenter) * k))
+}
+
+/// The vertices the GPU generates for `rects` drawn into `viewport`, in draw
+/// order: a CPU mirror of the vertex shader.
+pub fn encode_geometry(rects: &[Rect], viewport: &Viewport) -> Vec<Vec2> {
+    rects
+        .iter()
+        .flat_map(|rect| enclosing_triangle(rect, viewport))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CASES: usize = 1000;
+
+    /// xorshift64*, so the cases are random-ish but the same on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> f32 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 40) as f32 / (1u64 << 24) as f32
+        }
+
+        fn range(&mut self, lo: f32, hi: f32) -> f32 {
+            lo + (hi - lo) * self.next()
+        }
+    }
+
+    fn random_viewport(rng: &mut Rng) -> Viewport {
+        Viewport {
+            size_px: [rng.range(16.0, 4000.0), rng.range(16.0, 4000.0)],
+            pixels_per_point: rng.range(0.5, 3.0),
+        }
+    }
+
+    // Somewhere in or around `viewport`, in points. At least a pixel in size,
+    // or f32 can't resolve the padding far from the origin.
+    fn random_rect(rng: &mut Rng, viewport: &Viewport) -> Rect {
+        let size = viewport.size();
+        let min_size = 2.0;
+        Rect {
+            center: [rng.range(0.0, size.x), rng.range(0.0, size.y)],
+            size: [rng.range(min_size, size.x), rng.range(min_size, size.y)],
+            orientation_radians: rng.range(-10.0, 10.0),
+            style: RectStyle::INHERIT,
+        }
+    }
+
+    fn cross(o: Vec2, u: Vec2, v: Vec2) -> f32 {
+        (u - o).x * (v - o).y - (u - o).y * (v - o).x
+    }
+
+    fn random_wave(rng: &mut Rng) -> WaveParams {
+        WaveParams {
+            period_seconds: rng.range(0.1, 20.0),
+            amplitude: rng.range(0.0, 1.0),
+            phase_spread: rng.range(0.0, 10.0),
+            easing: Easing::ALL[(rng.next() * 4.0) as usize % 4],
+            alternate_rotation: rng.next() < 0.5,
+            rotation_degrees_per_second: rng.range(-720.0, 720.0),
+            size_scale: rng.range(0.1, 5.0),
+            pulse_amplitude: rng.range(0.0, 0.9),
+            pulse_period_seconds: rng.range(0.1, 10.0),
+        }
+    }
+
+    #[test]
+    fn easings_peak_on_whole_turns() {
+        for easing in Easing::ALL {
+            // the sawtooth drops back to 0 right on the turn
+            if easing != Easing::Sawtooth {
+                assert!((easing.apply(0.0) - 1.0).abs() < 1e-6, "{easing:?}");
+                assert!((easing.apply(-3.0) - 1.0).abs() < 1e-6, "{easing:?}");
+                assert!(easing.apply(0.5).abs() < 1e-6, "{easing:?}");
+            }
+            assert!((easing.apply(0.25) - easing.apply(1.25)).abs() < 1e-5);
+            let mut rng = Rng(6);
+            for _ in 0..CASES {
+                let y = easing.apply(rng.range(-10.0, 10.0));
+                assert!((0.0..=1.0).contains(&y), "{easing:?}: {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn rects_are_within_bounds() {
+        let mut rng = Rng(1);
+        for _ in 0..CASES {
+            let (x0, y0) = (rng.range(-1.0, 0.9), rng.range(-1.0, 0.9));
+            let (x1, y1) = (rng.range(x0 + 0.01, 1.0), rng.range(y0 + 0.01, 1.0));
+            let steps = rng.range(1.0, 500.0) as u32;
+            let time = rng.range(0.0, 100.0);
+            let wave = random_wave(&mut rng);
+            let rects = make_rects(time, &wave, steps, x0, x1, y0, y1);
+            assert_eq!(rects.len(), steps as usize);
+            let points = Viewport {
+                size_px: [1.0, 1.0],
+                pixels_per_point: 1.0,
+            };
+            for r in &rects {
+                assert!(r.size[0] > 0.0 && r.size[1] > 0.0);
+                for corner in corners_px(r, &points) {
+                    // allow for rounding in the rotation
+                    let (x, y) = (corner.x, corner.y);
+                    assert!(x0 - 1e-5 <= x && x <= x1 + 1e-5, "{x} not in {x0}..{x1}");
+                    assert!(y0 - 1e-5 <= y && y <= y1 + 1e-5, "{y} not in {y0}..{y1}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_waves_are_clamped() {
+        let wave = WaveParams {
+            period_seconds: 0.0,
+            size_scale: -1.0,
+            pulse_amplitude: 5.0,
+            pulse_period_seconds: 0.0,
+            ..Default::default()
+        };
+        for r in make_rects(1.0, &wave, 10, 0.0, 100.0, 0.0, 100.0) {
+            assert!(r.center.iter().chain(&r.size).all(|x| x.is_finite()));
+            assert!(r.size[0] > 0.0 && r.size[1] > 0.0);
+        }
+    }
+
+    #[test]
+    fn viewport_maps_to_all_of_clip_space() {
+        let viewport = Viewport::from_points(vec2(200.0, 100.0), 1.5);
+        assert_eq!(viewport.size_px, [300.0, 150.0]);
+        assert_eq!(viewport.size(), vec2(200.0, 100.0));
+        assert_eq!(viewport.px_to_clip(Vec2::ZERO), vec2(-1.0, 1.0));
+        assert_eq!(viewport.px_to_clip(vec2(300.0, 150.0)), vec2(1.0, -1.0));
+        assert_eq!(viewport.px_to_clip(vec2(150.0, 75.0)), Vec2::ZERO);
+        let p = vec2(12.0, 34.0);
+        assert!((viewport.clip_to_px(viewport.px_to_clip(p)) - p).length() < 1e-4);
+    }
+
+    // The corners of `rect`, in pixels.
+    fn corners_px(rect: &Rect, viewport: &Viewport) -> [Vec2; 4] {
+        let half_size = 0.5 * Vec2::from(rect.size);
+        let (s, c) = rect.orientation_radians.sin_cos();
+        [
+            vec2(-1.0, -1.0),
+            vec2(1.0, -1.0),
+            vec2(1.0, 1.0),
+            vec2(-1.0, 1.0),
+        ]
+        .map(|corner| {
+            let p = corner * half_size;
+            let q = vec2(p.x * c - p.y * s, p.x * s + p.y * c) + Vec2::from(rect.center);
+            q * viewport.pixels_per_point
+        })
+    }
+
+    // Distances in pixels from the sides of `rect`'s triangle to its closest
+    // corner, positive inside.
+    fn padding_px(rect: &Rect, viewport: &Viewport) -> [f32; 3] {
+        let triangle = enclosing_triangle(rect, viewport).map(|v| viewport.clip_to_px(v));
+        let corners = corners_px(rect, viewport);
+        std::array::from_fn(|i| {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            corners
+                .iter()
+                // distance inside the edge a->b, for a triangle that's
+                // counter-clockwise in clip space and so clockwise in pixels
+                .map(|&q| -cross(a, b, q) / (b - a).length())
+                .fold(f32::MAX, f32::min)
+        })
+    }
+
+    // Both shapes are convex, so the rect is covered with room to anti-alias
+    // its edges if every corner is at least the padding inside every side.
+    // Each side touches the rect before padding, so the closest corner is
+    // exactly the padding away.
+    #[test]
+    fn triangle_covers_rect_with_padding() {
+        let mut rng = Rng(2);
+        for _ in 0..CASES {
+            let viewport = random_viewport(&mut rng);
+            let rect = random_rect(&mut rng, &viewport);
+            for (i, padding) in padding_px(&rect, &viewport).into_iter().enumerate() {
+                assert!(
+                    (padding - AA_PADDING_PX).abs() < 1e-2,
+                    "{rect:?} in {viewport:?}: side {i} is {padding} px from the rect"
+                );
+            }
+        }
+    }
+
+    // The pipeline culls clockwise triangles.
+    #[test]
+    fn triangles_are_counter_clockwise() {
+        let mut rng = Rng(3);
+        for _ in 0..CASES {
+            let viewport = random_viewport(&mut rng);
+            let rect = random_rect(&mut rng, &viewport);
+            let [a, b, c] = enclosing_triangle(&rect, &viewport);
+            assert!(cross(a, b, c) > 0.0, "{rect:?}");
+        }
+    }
+
+    #[test]
+    fn three_vertices_per_rect() {
+        let mut rng = Rng(4);
+        for steps in [0, 1, 2, 17, 1000] {
+            let viewport = random_viewport(&mut rng);
+            let [w, h] = viewport.size_px.map(|v| v / viewport.pixels_per_point);
+            let wave = WaveParams::default();
+            let rects = make_rects(rng.range(0.0, 10.0), &wave, steps, 0.0, w, 0.0, h);
+            let vertices = encode_geometry(&rects, &viewport);
+            assert_eq!(vertices.len(), (VERTICES_PER_RECT * steps) as usize);
+            for (rect, triangle) in rects.iter().zip(vertices.chunks_exact(3)) {
+                assert_eq!(triangle, enclosing_triangle(rect, &viewport));
+            }
+        }
+    }
+}
@@ -1,2 +1,9 @@
+#[cfg(not(target_arch = "wasm32"))]
+pub mod export;
+mod fallback;
+pub mod geometry;
 mod painter;
+pub mod shading;
 pub mod ui;
+
+pub use painter::RectPainterSettings;
@@ -1,20 +1,22 @@
-use eframe::{
-    egui_wgpu::{self, RenderState},
-    wgpu::Queue,
-};
+use std::collections::HashMap;
+
+use eframe::{egui_wgpu, wgpu::Queue};
+use egui::{vec2, Vec2};
 use egui_wgpu::wgpu;
 use log::trace;
 use serde::{Deserialize, Serialize};
 use wgpu::{
-    util::{BufferInitDescriptor, DeviceExt},
-    vertex_attr_array, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingType, BlendState, Buffer, BufferBindingType, BufferDescriptor,
-    BufferUsages, ColorTargetState, ColorWrites, Face, FragmentState, FrontFace, IndexFormat,
-    MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PrimitiveTopology,
-    RenderPass, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource,
-    ShaderStages, VertexAttribute, VertexBufferLayout, VertexState, VertexStepMode,
+    vertex_attr_array, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendState, Buffer,
+    BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, Device, Face,
+    FragmentState, FrontFace, MultisampleState, PipelineLayout, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, RenderPass, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat,
+    VertexAttribute, VertexBufferLayout, VertexState, VertexStepMode,
 };
 
+use super::geometry::VERTICES_PER_RECT;
+
 unsafe fn as_u8_slice<T>(x: &[T]) -> &[u8] {
     std::slice::from_raw_parts(x.as_ptr() as *const u8, std::mem::size_of_val(x))
 }
@@ -23,37 +25,180 @@ unsafe fn as_raw_bytes<T>(x: &T) -> &[u8] {
     std::slice::from_raw_parts(x as *const T as *const u8, std::mem::size_of::<T>())
 }
 
+/// Per-instance data for the rect painter.
+///
+/// Positions and sizes are in egui points, relative to the top left of the
+/// [`Viewport`], with y pointing down. So positive orientations turn the rect
+/// clockwise on screen.
+///
+/// Each instance is drawn as a single triangle that encloses the rectangle.
+/// The triangle is generated in the vertex shader from these values.
 #[repr(C)]
-pub struct Vertex {
-    pub xyz: [f32; 3],
-    pub uv: [f32; 2],
+#[derive(Debug, Copy, Clone)]
+pub struct Rect {
+    pub center: [f32; 2],
+    pub size: [f32; 2],
+    pub orientation_radians: f32,
+    pub style: RectStyle,
 }
 
-impl Vertex {
-    const ATTRS: [VertexAttribute; 2] = vertex_attr_array![
-        0 => Float32x3,
-        1 => Float32x2
+impl Rect {
+    const ATTRS: [VertexAttribute; 7] = vertex_attr_array![
+        0 => Float32x2,
+        1 => Float32x2,
+        2 => Float32,
+        3 => Float32x4,
+        4 => Float32x4,
+        5 => Float32,
+        6 => Float32,
     ];
 
     fn layout<'a>() -> VertexBufferLayout<'a> {
         VertexBufferLayout {
             array_stride: std::mem::size_of::<Self>() as _,
-            step_mode: VertexStepMode::Vertex,
+            step_mode: VertexStepMode::Instance,
             attributes: &Self::ATTRS,
         }
     }
 }
 
+/// Per-rect style, with sizes in points like the rect itself.
+///
+/// Fields with negative values (negative alpha for the colors) fall back to
+/// the painter's [`RectPainterSettings`], so the default inherits everything.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct RectStyle {
+    pub fill: [f32; 4],
+    pub edge: [f32; 4],
+    pub line_width: f32,
+    pub corner_radius: f32,
+}
+
+impl RectStyle {
+    pub const INHERIT: Self = Self {
+        fill: [0.0, 0.0, 0.0, -1.0],
+        edge: [0.0, 0.0, 0.0, -1.0],
+        line_width: -1.0,
+        corner_radius: -1.0,
+    };
+}
+
+impl RectStyle {
+    /// Fills in the inherited fields from `defaults`, as the shader does.
+    pub fn resolve(&self, defaults: &RectPainterSettings) -> RectPainterSettings {
+        fn pick<T>(value: T, default: T, inherit: bool) -> T {
+            if inherit {
+                default
+            } else {
+                value
+            }
+        }
+        RectPainterSettings {
+            fill: pick(self.fill, defaults.fill, self.fill[3] < 0.0),
+            edge: pick(self.edge, defaults.edge, self.edge[3] < 0.0),
+            line_width: pick(self.line_width, defaults.line_width, self.line_width < 0.0),
+            corner_radius: pick(
+                self.corner_radius,
+                defaults.corner_radius,
+                self.corner_radius < 0.0,
+            ),
+        }
+    }
+}
+
+impl Default for RectStyle {
+    fn default() -> Self {
+        Self::INHERIT
+    }
+}
+
+/// Default style for all the rects drawn by a painter.
+///
+/// Sizes are in points, and are scaled to pixels by the viewport's
+/// `pixels_per_point` when drawn.
 #[repr(C, align(16))]
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct RectPainterSettings {
     pub edge: [f32; 4],
     pub fill: [f32; 4],
-    pub line_width_px: f32,
-    pub corner_radius_px: f32,
+    // Saved by older versions in pixels, which were points at 1x.
+    #[serde(alias = "line_width_px")]
+    pub line_width: f32,
+    #[serde(alias = "corner_radius_px")]
+    pub corner_radius: f32,
 }
 
 impl RectPainterSettings {
+    /// The style with its sizes in pixels, as the fragment shader uses it.
+    pub fn to_px(&self, pixels_per_point: f32) -> Self {
+        Self {
+            line_width: self.line_width * pixels_per_point,
+            corner_radius: self.corner_radius * pixels_per_point,
+            ..*self
+        }
+    }
+}
+
+impl Default for RectPainterSettings {
+    fn default() -> Self {
+        Self {
+            edge: [0.0, 0.0, 0.0, 1.0],
+            fill: [1.0, 1.0, 1.0, 1.0],
+            line_width: 2.0,
+            corner_radius: 0.0,
+        }
+    }
+}
+
+/// The area the rects are drawn into, which maps their layout in points to
+/// pixels and clip space.
+///
+/// For a paint callback this is `PaintCallbackInfo::viewport`, which is the
+/// callback's rect.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewport {
+    /// Size in physical pixels.
+    pub size_px: [f32; 2],
+    pub pixels_per_point: f32,
+}
+
+impl Viewport {
+    /// A viewport `size` points in size.
+    pub fn from_points(size: Vec2, pixels_per_point: f32) -> Self {
+        Self {
+            size_px: (size * pixels_per_point).into(),
+            pixels_per_point,
+        }
+    }
+
+    /// Size in points.
+    pub fn size(&self) -> Vec2 {
+        Vec2::from(self.size_px) / self.pixels_per_point
+    }
+
+    /// Maps a position in pixels to clip space, as the vertex shader does.
+    pub fn px_to_clip(&self, p: Vec2) -> Vec2 {
+        p / Vec2::from(self.size_px) * vec2(2.0, -2.0) + vec2(-1.0, 1.0)
+    }
+
+    /// The inverse of [`Self::px_to_clip`].
+    pub fn clip_to_px(&self, p: Vec2) -> Vec2 {
+        (p - vec2(-1.0, 1.0)) / vec2(2.0, -2.0) * Vec2::from(self.size_px)
+    }
+}
+
+/// Everything in the painter's uniform buffer. Matches `Uniforms` in
+/// `painter.wgsl`.
+#[repr(C, align(16))]
+#[derive(Debug, Copy, Clone)]
+struct Uniforms {
+    settings: RectPainterSettings,
+    viewport: Viewport,
+}
+
+impl Uniforms {
     fn descriptor<'a>() -> BufferDescriptor<'a> {
         BufferDescriptor {
             label: None,
@@ -64,84 +209,151 @@ impl RectPainterSettings {
     }
 }
 
-impl Default for RectPainterSettings {
-    fn default() -> Self {
+/// A GPU buffer that is reallocated as the data written to it changes size.
+///
+/// Capacity doubles when the data no longer fits, and halves when less than a
+/// quarter of it is in use, so resizing is rare when the size oscillates.
+struct GrowableBuffer {
+    label: &'static str,
+    usage: BufferUsages,
+    buffer: Buffer,
+}
+
+impl GrowableBuffer {
+    // bytes
+    const MIN_CAPACITY: u64 = 1024;
+
+    fn new(device: &Device, label: &'static str, usage: BufferUsages) -> Self {
+        let usage = usage | BufferUsages::COPY_DST;
+        let buffer = Self::allocate(device, label, usage, Self::MIN_CAPACITY);
         Self {
-            edge: [0.0, 0.0, 0.0, 1.0],
-            fill: [1.0, 1.0, 1.0, 1.0],
-            line_width_px: 2.0,
-            corner_radius_px: 0.0,
+            label,
+            usage,
+            buffer,
+        }
+    }
+
+    fn allocate(device: &Device, label: &str, usage: BufferUsages, size: u64) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some(label),
+            size,
+            usage,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Capacity in bytes.
+    fn capacity(&self) -> u64 {
+        self.buffer.size()
+    }
+
+    /// The capacity to hold `needed` bytes in a buffer that holds `current`.
+    ///
+    /// Always a power of two times [`Self::MIN_CAPACITY`], so it's a multiple
+    /// of `wgpu::COPY_BUFFER_ALIGNMENT`.
+    fn next_capacity(current: u64, needed: u64) -> u64 {
+        let mut capacity = current.max(Self::MIN_CAPACITY);
+        while needed > capacity {
+            capacity *= 2;
+        }
+        while capacity > Self::MIN_CAPACITY && 4 * needed < capacity {
+            capacity /= 2;
+        }
+        capacity
+    }
+
+    fn write(&mut self, device: &Device, queue: &Queue, data: &[u8]) {
+        let capacity = Self::next_capacity(self.capacity(), data.len() as u64);
+        if capacity != self.capacity() {
+            trace!(
+                "{}: reallocating {} -> {} bytes",
+                self.label,
+                self.capacity(),
+                capacity
+            );
+            self.buffer = Self::allocate(device, self.label, self.usage, capacity);
+        }
+        if !data.is_empty() {
+            queue.write_buffer(&self.buffer, 0, data);
         }
     }
 }
 
-pub struct RectPainter {
+/// The rect painter's shader as it was when the app was built.
+pub const SHADER_SOURCE: &str = include_str!("painter.wgsl");
+
+/// The parts of the rect painter that are shared by every widget: the shader,
+/// the render pipeline and the layout of the bindings.
+pub struct RectPipeline {
     pipeline: RenderPipeline,
-    bind_group: BindGroup,
-    uniforms: Buffer,
-    vertices: Buffer,
-    vertex_count: usize,
-    indexes: Buffer,
-    index_count: usize,
+    bind_group_layout: BindGroupLayout,
+    // For `reload`, which is native only.
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pipeline_layout: PipelineLayout,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    target_format: TextureFormat,
 }
 
-impl RectPainter {
-    pub(crate) fn new(rc: &RenderState) -> Self {
+impl RectPipeline {
+    pub fn new(device: &Device, target_format: TextureFormat) -> Self {
         // Memory layout for the painter
-        let layout = rc
-            .device
-            .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("my painter bind group layout"),
-                entries: &[
-                    // Color
-                    BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::FRAGMENT | ShaderStages::VERTEX,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("my painter bind group layout"),
+            entries: &[
+                // Color
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT | ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
                     },
-                ],
-            });
-
-        let uniforms = rc.device.create_buffer(&RectPainterSettings::descriptor());
+                    count: None,
+                },
+            ],
+        });
 
-        let bind_group = rc.device.create_bind_group(&BindGroupDescriptor {
-            label: Some("My painter bind group"),
-            layout: &layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: uniforms.as_entire_binding(),
-            }],
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("My Painter Render Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
         });
+        let pipeline =
+            Self::create_pipeline(device, &pipeline_layout, target_format, SHADER_SOURCE);
 
-        let module = &rc.device.create_shader_module(ShaderModuleDescriptor {
+        Self {
+            pipeline,
+            bind_group_layout,
+            pipeline_layout,
+            target_format,
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        layout: &PipelineLayout,
+        target_format: TextureFormat,
+        source: &str,
+    ) -> RenderPipeline {
+        let module = &device.create_shader_module(ShaderModuleDescriptor {
             label: Some("My Painter shader module"),
-            source: ShaderSource::Wgsl(include_str!("painter.wgsl").into()),
+            source: ShaderSource::Wgsl(source.into()),
         });
 
-        let pipeline = rc.device.create_render_pipeline(&RenderPipelineDescriptor {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
             label: Some("My Painter Render Pipeline"),
-            layout: Some(
-                &rc.device.create_pipeline_layout(&PipelineLayoutDescriptor {
-                    label: Some("My Painter Render Pipeline Layout"),
-                    bind_group_layouts: &[&layout],
-                    push_constant_ranges: &[],
-                }),
-            ),
+            layout: Some(layout),
             vertex: VertexState {
                 module,
                 entry_point: "vs",
-                buffers: &[Vertex::layout()],
+                buffers: &[Rect::layout()],
             },
             fragment: Some(FragmentState {
                 module,
                 entry_point: "fs",
                 targets: &[Some(ColorTargetState {
-                    format: rc.target_format,
+                    format: target_format,
                     blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
                     write_mask: ColorWrites::ALL,
                 })],
@@ -162,72 +374,201 @@ impl RectPainter {
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
-        });
+        })
+    }
 
-        // Geometry buffers
-        let vertices = rc.device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Painter vertex buffer"),
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-            contents: &[0; 6000], // FIXME: reallocation?
-        });
+    /// Recompiles the pipeline from `source`.
+    ///
+    /// On error the current pipeline is kept. Errors are caught with an error
+    /// scope, which can only be waited on synchronously on native.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload(&mut self, device: &Device, source: &str) -> Result<(), wgpu::Error> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline =
+            Self::create_pipeline(device, &self.pipeline_layout, self.target_format, source);
+        match pollster::block_on(device.pop_error_scope()) {
+            Some(error) => Err(error),
+            None => {
+                self.pipeline = pipeline;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The per-widget state of the rect painter: its uniforms and instances.
+pub struct RectPainter {
+    bind_group: BindGroup,
+    uniforms: Buffer,
+    instances: GrowableBuffer,
+    instance_count: usize,
+}
+
+impl RectPainter {
+    pub fn new(device: &Device, pipeline: &RectPipeline) -> Self {
+        let uniforms = device.create_buffer(&Uniforms::descriptor());
 
-        let indexes = rc.device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Painter index buffer"),
-            usage: BufferUsages::INDEX | BufferUsages::COPY_DST,
-            contents: &[0; 6000], // FIXME: reallocation?
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("My painter bind group"),
+            layout: &pipeline.bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: uniforms.as_entire_binding(),
+            }],
         });
 
+        // Per-instance buffer. This gets resized in `set_rects` as needed.
+        let instances =
+            GrowableBuffer::new(device, "Painter instance buffer", BufferUsages::VERTEX);
+
         Self {
-            pipeline,
             bind_group,
             uniforms,
-            vertices,
-            vertex_count: 0,
-            indexes,
-            index_count: 0,
+            instances,
+            instance_count: 0,
         }
     }
 
-    pub fn set_geometry(&mut self, queue: &Queue, vertices: &[Vertex], indexes: &[u32]) {
+    /// Uploads the rectangles, reallocating the instance buffer on `device`
+    /// if it is too small (or much too large).
+    pub fn set_rects(&mut self, device: &Device, queue: &Queue, rects: &[Rect]) {
         puffin::profile_function!();
-        self.vertex_count = vertices.len();
-        self.index_count = indexes.len();
-        queue.write_buffer(&self.vertices, 0, unsafe { as_u8_slice(vertices) });
-        queue.write_buffer(&self.indexes, 0, unsafe { as_u8_slice(indexes) });
+        self.instance_count = rects.len();
+        self.instances
+            .write(device, queue, unsafe { as_u8_slice(rects) });
+    }
+
+    /// The number of rectangles that fit in the currently allocated instance
+    /// buffer.
+    pub fn capacity(&self) -> usize {
+        self.instances.capacity() as usize / std::mem::size_of::<Rect>()
     }
 
-    pub fn set_uniforms(&self, queue: &Queue, settings: &RectPainterSettings) {
+    /// Sets the default style, and the viewport the rects are drawn into.
+    pub fn set_uniforms(&self, queue: &Queue, settings: &RectPainterSettings, viewport: Viewport) {
         puffin::profile_function!();
-        queue.write_buffer(&self.uniforms, 0, unsafe { as_raw_bytes(settings) });
+        let uniforms = Uniforms {
+            settings: *settings,
+            viewport,
+        };
+        queue.write_buffer(&self.uniforms, 0, unsafe { as_raw_bytes(&uniforms) });
     }
 
     /// Set up the render pass for the frame.
-    pub fn paint<'rp>(&'rp self, pass: &mut RenderPass<'rp>) {
+    pub fn paint<'rp>(&'rp self, pipeline: &'rp RectPipeline, pass: &mut RenderPass<'rp>) {
         puffin::profile_function!();
-        pass.set_pipeline(&self.pipeline);
+        if self.instance_count == 0 {
+            return;
+        }
+        pass.set_pipeline(&pipeline.pipeline);
         pass.set_bind_group(0, &self.bind_group, &[]);
         trace!(
-            "vertex count {} size {} {:?}",
-            self.vertex_count,
-            self.vertices.size(),
-            self.vertices
+            "instance count {} size {} {:?}",
+            self.instance_count,
+            self.instances.capacity(),
+            self.instances.buffer
         );
         pass.set_vertex_buffer(
             0,
-            self.vertices
-                .slice(..(std::mem::size_of::<Vertex>() * self.vertex_count) as u64),
-        );
-        trace!(
-            "index count {} size {} {:?}",
-            self.index_count,
-            self.indexes.size(),
-            self.indexes
+            self.instances
+                .buffer
+                .slice(..(std::mem::size_of::<Rect>() * self.instance_count) as u64),
         );
-        pass.set_index_buffer(
-            self.indexes
-                .slice(..(std::mem::size_of::<u32>() * self.index_count) as u64),
-            IndexFormat::Uint32,
-        );
-        pass.draw_indexed(0..self.index_count as u32, 0, 0..1);
+        // One triangle per rect. The vertices are generated in the shader.
+        pass.draw(0..VERTICES_PER_RECT, 0..self.instance_count as u32);
+    }
+}
+
+/// Rect painting resources for all the widgets, stored in
+/// `egui_wgpu::CallbackResources`.
+///
+/// The pipeline is compiled once and shared. Each widget gets its own
+/// [`RectPainter`] keyed by the widget's id.
+pub struct RectPainters {
+    pipeline: RectPipeline,
+    painters: HashMap<u64, RectPainter>,
+}
+
+impl RectPainters {
+    pub fn new(device: &Device, target_format: TextureFormat) -> Self {
+        Self {
+            pipeline: RectPipeline::new(device, target_format),
+            painters: HashMap::new(),
+        }
+    }
+
+    /// The state for widget `id`, allocated on first use.
+    pub fn get_or_insert(&mut self, device: &Device, id: u64) -> &mut RectPainter {
+        let pipeline = &self.pipeline;
+        self.painters
+            .entry(id)
+            .or_insert_with(|| RectPainter::new(device, pipeline))
+    }
+
+    /// Releases the state for widget `id`.
+    pub fn remove(&mut self, id: u64) {
+        self.painters.remove(&id);
+    }
+
+    /// True if no widget has any state allocated.
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.painters.is_empty()
+    }
+
+    /// Recompiles the shared pipeline from `source`. See [`RectPipeline::reload`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn reload(&mut self, device: &Device, source: &str) -> Result<(), wgpu::Error> {
+        self.pipeline.reload(device, source)
+    }
+
+    pub fn paint<'rp>(&'rp self, id: u64, pass: &mut RenderPass<'rp>) {
+        if let Some(painter) = self.painters.get(&id) {
+            painter.paint(&self.pipeline, pass);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: u64 = GrowableBuffer::MIN_CAPACITY;
+
+    fn next_capacity(current: u64, needed: u64) -> u64 {
+        GrowableBuffer::next_capacity(current, needed)
+    }
+
+    #[test]
+    fn capacity_doubles_until_the_data_fits() {
+        assert_eq!(next_capacity(MIN, MIN), MIN);
+        assert_eq!(next_capacity(MIN, MIN + 1), 2 * MIN);
+        assert_eq!(next_capacity(MIN, 5 * MIN), 8 * MIN);
+        assert_eq!(next_capacity(2 * MIN, 100 * MIN), 128 * MIN);
+    }
+
+    #[test]
+    fn capacity_shrinks_below_a_quarter_in_use() {
+        // half and exactly a quarter full stay put
+        assert_eq!(next_capacity(8 * MIN, 4 * MIN), 8 * MIN);
+        assert_eq!(next_capacity(8 * MIN, 2 * MIN), 8 * MIN);
+        assert_eq!(next_capacity(8 * MIN, 2 * MIN - 1), 4 * MIN);
+        assert_eq!(next_capacity(64 * MIN, MIN), 4 * MIN);
+    }
+
+    #[test]
+    fn capacity_stays_at_least_the_minimum() {
+        assert_eq!(next_capacity(MIN, 0), MIN);
+        assert_eq!(next_capacity(16 * MIN, 0), MIN);
+        assert_eq!(next_capacity(MIN, 1), MIN);
+    }
+
+    #[test]
+    fn capacity_is_aligned_for_copies() {
+        for needed in [0, 1, 3, 1000, 4097, 123_457] {
+            let capacity = next_capacity(MIN, needed);
+            assert!(capacity >= needed);
+            assert_eq!(capacity % wgpu::COPY_BUFFER_ALIGNMENT, 0);
+        }
     }
 }
@@ -1,32 +1,118 @@
 struct Settings {
     edge: vec4<f32>,
     fill: vec4<f32>,
-    line_width_px: f32,
-    corner_radius_px: f32
+    line_width: f32,
+    corner_radius: f32
+}
+
+// The area the rects are drawn into. Rects are laid out in egui points
+// relative to its top left, y down.
+struct Viewport {
+    size_px: vec2<f32>,
+    pixels_per_point: f32,
+}
+
+struct Uniforms {
+    settings: Settings,
+    viewport: Viewport,
 }
 
 @group(0) @binding(0)
-var<uniform> settings: Settings;
+var<uniform> uniforms: Uniforms;
+
+// Margin around each rect's triangle so the fragment shader has room to
+// anti-alias the rect's edges on every side.
+const AA_PADDING_PX: f32 = 1.0;
 
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coords: vec2<f32>,
+// Negative style values (alpha for colors) inherit from `settings`. Like
+// the rect, its style's sizes are in points.
+struct RectInstance {
+    @location(0) center: vec2<f32>,
+    @location(1) size: vec2<f32>,
+    @location(2) orientation_radians: f32,
+    @location(3) fill: vec4<f32>,
+    @location(4) edge: vec4<f32>,
+    @location(5) line_width: f32,
+    @location(6) corner_radius: f32,
 }
 
 struct VertexOutput {
     // position get mapped from clip space to viewport (pixel) space between 
     // pipleline stages (looks like)
     @builtin(position) position: vec4<f32>,
-    @location(0) tex_coords: vec2<f32>,
+    // position in pixels relative to the rect's center, along its axes
+    @location(0) local_px: vec2<f32>,
+    @location(1) @interpolate(flat) fill: vec4<f32>,
+    @location(2) @interpolate(flat) edge: vec4<f32>,
+    @location(3) @interpolate(flat) line_width_px: f32,
+    @location(4) @interpolate(flat) corner_radius_px: f32,
+    @location(5) @interpolate(flat) half_size_px: vec2<f32>,
 }
 
+// Each rect is drawn as an isosceles right triangle that encloses it.
+// The right angle sits on one corner of the rect, the legs run along two of
+// its sides and the hypotenuse touches the opposite corner. The triangle is
+// then grown so that each side is AA_PADDING_PX further out.
+//
+// The work is done in pixels, which are y down like points, so the corners
+// are listed clockwise to come out counter-clockwise in clip space.
 @vertex
 fn vs(
-    model: VertexInput,
+    @builtin(vertex_index) vertex_index: u32,
+    rect: RectInstance,
 ) -> VertexOutput {
+    let settings = uniforms.settings;
+    let viewport = uniforms.viewport;
+    var corners = array<vec2<f32>, 3>(
+        vec2(0.0, 0.0),
+        vec2(0.0, 2.0),
+        vec2(2.0, 0.0),
+    );
+    let center = rect.center * viewport.pixels_per_point;
+    let half_size = 0.5 * rect.size * viewport.pixels_per_point;
+    let side = half_size.x + half_size.y;
+    let c = cos(rect.orientation_radians);
+    let s = sin(rect.orientation_radians);
+
+    var v: array<vec2<f32>, 3>;
+    for (var i = 0; i < 3; i++) {
+        let p = corners[i] * side - half_size;
+        // rotate about the center, then translate
+        v[i] = vec2(p.x * c - p.y * s, p.x * s + p.y * c) + center;
+    }
+
+    // Scaling about the incenter moves every side out by the same distance.
+    let a = distance(v[1], v[2]);
+    let b = distance(v[2], v[0]);
+    let e = distance(v[0], v[1]);
+    let perimeter = a + b + e;
+    let incenter = (a * v[0] + b * v[1] + e * v[2]) / perimeter;
+    let u = v[1] - v[0];
+    let w = v[2] - v[0];
+    let inradius = abs(u.x * w.y - u.y * w.x) / perimeter;
+    let k = 1.0 + AA_PADDING_PX / max(inradius, 1e-6);
+    let q = incenter + (v[vertex_index] - incenter) * k;
+
+    // back into the rect's frame
+    let d = q - center;
+
     var out: VertexOutput;
-    out.tex_coords = model.tex_coords;
-    out.position = vec4<f32>(model.position, 1.0);
+    out.local_px = vec2(d.x * c + d.y * s, -d.x * s + d.y * c);
+    out.half_size_px = half_size;
+    out.position = vec4<f32>(q / viewport.size_px * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    out.fill = select(settings.fill, rect.fill, rect.fill.a >= 0.0);
+    out.edge = select(settings.edge, rect.edge, rect.edge.a >= 0.0);
+    // the style's sizes are in points too
+    out.line_width_px = viewport.pixels_per_point * select(
+        settings.line_width,
+        rect.line_width,
+        rect.line_width >= 0.0
+    );
+    out.corner_radius_px = viewport.pixels_per_point * select(
+        settings.corner_radius,
+        rect.corner_radius,
+        rect.corner_radius >= 0.0
+    );
     return out;
 }
 
@@ -54,26 +140,17 @@ fn premultiply(src: vec4<f32>) -> vec4<f32> {
 
 @fragment
 fn fs(in: VertexOutput) -> @location(0) vec4<f32> {
-    // Scale so distance is evaluated in viewport space.
-    // That lets us evaluate the line width in px.
-    // Gradient can come out negative depending on triangle orientation,
-    // so take the absolute value.
-    let duvdx = dpdx(in.tex_coords);  // dvu/dx (tex coord units/viewport pixel)
-    let duvdy = dpdy(in.tex_coords);  // duv/dy
-    let dx = length(vec2(duvdx.x, duvdy.x));
-    let dy = length(vec2(duvdx.y, duvdy.y));
-    let s = vec2(dx, dy);
-
-    let d = sd_round_box(in.tex_coords.xy / s, 0.5 / s, settings.corner_radius_px);
+    // distance to the outline in pixels
+    let d = sd_round_box(in.local_px, in.half_size_px, in.corner_radius_px);
 
-    let fill = mix(settings.fill, vec4(0.0), saturate(d));
+    let fill = mix(in.fill, vec4(0.0), saturate(d));
 
-    if settings.line_width_px < 1e-3 {
+    if in.line_width_px < 1e-3 {
         return premultiply(fill);
     } else {
         // attenuate alpha for sub-pixel line-widths
-        let edge = vec4(settings.edge.xyz, settings.edge.w * saturate(settings.line_width_px));
-        let eps = settings.line_width_px + d; // distance from inner edge of boundary
+        let edge = vec4(in.edge.xyz, in.edge.w * saturate(in.line_width_px));
+        let eps = in.line_width_px + d; // distance from inner edge of boundary
         return premultiply(mix(mix(edge, fill, saturate(-eps)), vec4(0.0), saturate(d)));
     }
 }
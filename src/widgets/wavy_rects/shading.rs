@@ -0,0 +1,250 @@
+//! The rect painter's shading math on the CPU.
+//!
+//! Mirrors `painter.wgsl`, so keep the two in sync. [`rasterize`] uses it to
+//! render reference images without a GPU; the egui fallback uses the same
+//! color rules.
+
+use egui::{vec2, Color32, ColorImage, Vec2};
+
+use super::{
+    geometry::encode_geometry,
+    painter::{Rect, RectPainterSettings, Viewport},
+};
+
+/// Lines thinner than this aren't drawn at all.
+pub const MIN_LINE_WIDTH_PX: f32 = 1e-3;
+
+/// Signed distance from `p` to a box centered at the origin with half size
+/// `b`. Negative inside.
+pub fn sd_box(p: Vec2, b: Vec2) -> f32 {
+    let d = p.abs() - b;
+    d.max(Vec2::ZERO).length() + d.max_elem().min(0.0)
+}
+
+/// Signed distance from `p` to a box centered at the origin with half size
+/// `b` and corners rounded with radius `r`. Negative inside.
+///
+/// `p`, `b` and `r` should all be in the same units.
+pub fn sd_round_box(p: Vec2, b: Vec2, r: f32) -> f32 {
+    let q = p.abs() - b + Vec2::splat(r);
+    q.max(Vec2::ZERO).length() + q.max_elem().min(0.0) - r
+}
+
+pub fn premultiply([r, g, b, a]: [f32; 4]) -> [f32; 4] {
+    [r * a, g * a, b * a, a]
+}
+
+/// The edge color, with alpha attenuated for sub-pixel line widths.
+///
+/// `style`'s sizes are in pixels, see [`RectPainterSettings::to_px`].
+pub fn edge_color(style: &RectPainterSettings) -> [f32; 4] {
+    let [r, g, b, a] = style.edge;
+    [r, g, b, a * style.line_width.clamp(0.0, 1.0)]
+}
+
+/// The premultiplied color `d` pixels from a rect's outline (negative
+/// inside), as the fragment shader computes it.
+///
+/// `style`'s sizes are in pixels, see [`RectPainterSettings::to_px`].
+pub fn shade(d: f32, style: &RectPainterSettings) -> [f32; 4] {
+    let fill = mix(style.fill, [0.0; 4], d.clamp(0.0, 1.0));
+    if style.line_width < MIN_LINE_WIDTH_PX {
+        return premultiply(fill);
+    }
+    // distance from the inner edge of the boundary
+    let eps = style.line_width + d;
+    let inner = mix(edge_color(style), fill, (-eps).clamp(0.0, 1.0));
+    premultiply(mix(inner, [0.0; 4], d.clamp(0.0, 1.0)))
+}
+
+fn mix(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    std::array::from_fn(|i| a[i] + t * (b[i] - a[i]))
+}
+
+pub(super) fn in_triangle(p: Vec2, triangle: &[Vec2]) -> bool {
+    let [a, b, c] = triangle else {
+        return false;
+    };
+    let cross = |o: Vec2, u: Vec2, v: Vec2| (u - o).x * (v - o).y - (u - o).y * (v - o).x;
+    let (d0, d1, d2) = (cross(*a, *b, p), cross(*b, *c, p), cross(*c, *a, p));
+    (d0 >= 0.0 && d1 >= 0.0 && d2 >= 0.0) || (d0 <= 0.0 && d1 <= 0.0 && d2 <= 0.0)
+}
+
+/// Renders `rects` into an image the size of `viewport` the way the GPU
+/// does: pixels are shaded where their centers fall inside each rect's
+/// enclosing triangle, and blended in order with premultiplied alpha into an
+/// 8-bit target.
+pub fn rasterize(
+    rects: &[Rect],
+    defaults: &RectPainterSettings,
+    viewport: &Viewport,
+) -> ColorImage {
+    puffin::profile_function!();
+    let [w, h] = viewport.size_px.map(|v| v.round() as usize);
+    let mut target = vec![[0.0f32; 4]; w * h];
+    let ppp = viewport.pixels_per_point;
+    let triangles = encode_geometry(rects, viewport);
+    for (rect, triangle) in rects.iter().zip(triangles.chunks_exact(3)) {
+        let style = rect.style.resolve(defaults).to_px(ppp);
+        let center = Vec2::from(rect.center) * ppp;
+        let half_size = 0.5 * Vec2::from(rect.size) * ppp;
+        let (s, c) = rect.orientation_radians.sin_cos();
+        let triangle = triangle
+            .iter()
+            .map(|&v| viewport.clip_to_px(v))
+            .collect::<Vec<_>>();
+        let (lo, hi) = triangle.iter().fold(
+            (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+            |(lo, hi), &v| (lo.min(v), hi.max(v)),
+        );
+        let cols = lo.x.floor().max(0.0) as usize..(hi.x.ceil().max(0.0) as usize).min(w);
+        let rows = lo.y.floor().max(0.0) as usize..(hi.y.ceil().max(0.0) as usize).min(h);
+        for y in rows {
+            for x in cols.clone() {
+                let q = vec2(x as f32 + 0.5, y as f32 + 0.5);
+                if !in_triangle(q, &triangle) {
+                    continue;
+                }
+                // back into the rect's frame
+                let d = q - center;
+                let local = vec2(d.x * c + d.y * s, -d.x * s + d.y * c);
+                let dist = sd_round_box(local, half_size, style.corner_radius);
+                let src = shade(dist, &style);
+                let dst = &mut target[y * w + x];
+                // premultiplied alpha blending, stored as 8 bits per channel
+                *dst = std::array::from_fn(|i| {
+                    let v = src[i] + dst[i] * (1.0 - src[3]);
+                    (v.clamp(0.0, 1.0) * 255.0).round() / 255.0
+                });
+            }
+        }
+    }
+    ColorImage {
+        size: [w, h],
+        pixels: target
+            .into_iter()
+            .map(|p| {
+                let [r, g, b, a] = p.map(|v| (v * 255.0).round() as u8);
+                Color32::from_rgba_premultiplied(r, g, b, a)
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(line_width: f32) -> RectPainterSettings {
+        RectPainterSettings {
+            edge: [1.0, 0.0, 0.0, 1.0],
+            fill: [0.0, 0.0, 1.0, 0.5],
+            line_width,
+            corner_radius: 0.0,
+        }
+    }
+
+    #[test]
+    fn round_box_distances() {
+        let b = vec2(2.0, 1.0);
+        assert_eq!(sd_round_box(Vec2::ZERO, b, 0.0), -1.0);
+        assert_eq!(sd_round_box(vec2(2.0, 0.0), b, 0.0), 0.0);
+        assert_eq!(sd_round_box(vec2(3.0, 0.0), b, 0.0), 1.0);
+        // outside a sharp corner the distance is to the corner
+        assert_eq!(sd_round_box(vec2(5.0, 5.0), b, 0.0), 5.0);
+        // rounding moves the corner in along the diagonal
+        let r = 0.5;
+        let corner = b - Vec2::splat(r) + Vec2::splat(r / 2f32.sqrt());
+        assert!(sd_round_box(corner, b, r).abs() < 1e-6);
+        assert!(sd_round_box(b, b, r) > 0.0);
+    }
+
+    #[test]
+    fn round_box_with_no_radius_is_a_box() {
+        let b = vec2(3.0, 0.5);
+        for p in [
+            vec2(0.1, 0.2),
+            vec2(-4.0, 1.0),
+            vec2(2.5, -0.4),
+            vec2(7.0, 9.0),
+        ] {
+            assert_eq!(sd_round_box(p, b, 0.0), sd_box(p, b));
+        }
+    }
+
+    #[test]
+    fn premultiply_scales_color_by_alpha() {
+        assert_eq!(premultiply([1.0, 0.5, 0.25, 0.5]), [0.5, 0.25, 0.125, 0.5]);
+    }
+
+    #[test]
+    fn shade_regions() {
+        let style = style(2.0);
+        // well inside: fill
+        assert_eq!(shade(-5.0, &style), premultiply(style.fill));
+        // within a line width of the outline: edge
+        assert_eq!(shade(-1.0, &style), premultiply(style.edge));
+        // outside: nothing
+        assert_eq!(shade(1.0, &style), [0.0; 4]);
+        assert_eq!(shade(3.0, &style), [0.0; 4]);
+    }
+
+    #[test]
+    fn thin_lines_are_attenuated() {
+        let style = style(0.25);
+        assert_eq!(edge_color(&style)[3], 0.25);
+        // no line at all below the threshold
+        let style = self::style(0.0);
+        assert_eq!(shade(-0.5, &style), premultiply(style.fill));
+    }
+
+    #[test]
+    fn rasterize_axis_aligned_rect() {
+        // a 10x10 px rect in the middle of a 20x20 image
+        let rect = Rect {
+            center: [10.0, 10.0],
+            size: [10.0, 10.0],
+            orientation_radians: 0.0,
+            style: Default::default(),
+        };
+        let style = style(2.0);
+        let image = rasterize(
+            &[rect],
+            &style,
+            &Viewport::from_points(vec2(20.0, 20.0), 1.0),
+        );
+        let at = |x: usize, y: usize| image.pixels[y * 20 + x];
+        let fill = Color32::from_rgba_premultiplied(0, 0, 128, 128);
+        let edge = Color32::RED;
+        assert_eq!(at(10, 10), fill);
+        assert_eq!(at(10, 5), edge);
+        assert_eq!(at(10, 6), edge);
+        assert_eq!(at(10, 8), fill);
+        assert_eq!(at(2, 2), Color32::TRANSPARENT);
+    }
+
+    #[test]
+    fn style_sizes_scale_with_pixels_per_point() {
+        // the same rect as above at 2x, so the 2 point line is 4 px wide
+        let rect = Rect {
+            center: [10.0, 10.0],
+            size: [10.0, 10.0],
+            orientation_radians: 0.0,
+            style: Default::default(),
+        };
+        let style = style(2.0);
+        let image = rasterize(
+            &[rect],
+            &style,
+            &Viewport::from_points(vec2(20.0, 20.0), 2.0),
+        );
+        assert_eq!(image.size, [40, 40]);
+        let at = |x: usize, y: usize| image.pixels[y * 40 + x];
+        let fill = Color32::from_rgba_premultiplied(0, 0, 128, 128);
+        let edge = Color32::RED;
+        assert_eq!(at(20, 10), edge);
+        assert_eq!(at(20, 13), edge);
+        assert_eq!(at(20, 15), fill);
+        assert_eq!(at(20, 20), fill);
+    }
+}
@@ -1,12 +1,20 @@
 use std::f32::consts::PI;
 
-use eframe::{egui_wgpu, CreationContext};
-use egui::{vec2, Align, Layout, Widget};
+use eframe::egui_wgpu::{self, CallbackResources};
+use egui::{vec2, Align, Layout, Vec2, Widget};
+use log::trace;
 use serde::{Deserialize, Serialize};
 
-use crate::widgets::player::{self, PlayerState};
+use crate::widgets::{
+    gpu::{self, next_id},
+    player::{self, PlayerState},
+};
 
-use super::painter::{RectPainter, RectPainterSettings, Vertex};
+use super::{
+    fallback,
+    geometry::{make_rects, Easing, WaveParams},
+    painter::{Rect, RectPainterSettings, RectPainters, Viewport},
+};
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Default)]
 #[serde(default)]
@@ -16,8 +24,55 @@ pub struct WavyRectanglesWithControls {
 }
 
 impl WavyRectanglesWithControls {
-    pub fn setup_renderer<'a>(&mut self, cc: &'a CreationContext<'a>) {
-        self.wavy_rectangles.setup_renderer(cc);
+    /// Frees the GPU resources used by this widget.
+    ///
+    /// Don't show the widget again in the same frame, or its paint callback
+    /// allocates them again.
+    pub fn release(&self, resources: &mut CallbackResources) {
+        self.wavy_rectangles.release(resources);
+    }
+}
+
+/// Shows `widgets` two to a row, each with a button to remove it.
+///
+/// Returns the widget that was removed, if any. It isn't shown in the frame
+/// it's removed in, so it can be released right away.
+pub fn show_panels(
+    ui: &mut egui::Ui,
+    widgets: &mut Vec<WavyRectanglesWithControls>,
+) -> Option<WavyRectanglesWithControls> {
+    let mut removed = None;
+    for (row, pair) in widgets.chunks_mut(2).enumerate() {
+        ui.columns(2, |columns| {
+            for (col, (ui, widget)) in columns.iter_mut().zip(pair).enumerate() {
+                if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                    removed = Some(2 * row + col);
+                } else {
+                    ui.add(widget);
+                }
+            }
+        });
+    }
+    removed.map(|i| widgets.remove(i))
+}
+
+/// Recompiles the pipeline shared by all the rect widgets from `source`,
+/// keeping the current one if it doesn't compile.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn reload_shader(render_state: &egui_wgpu::RenderState, source: &str) {
+    let mut renderer = render_state.renderer.write();
+    let resources = &mut renderer.callback_resources;
+    let Some(target_format) = gpu::target_format(resources) else {
+        return;
+    };
+    let device = &render_state.device;
+    let result = resources
+        .entry::<RectPainters>()
+        .or_insert_with(|| RectPainters::new(device, target_format))
+        .reload(device, source);
+    match result {
+        Ok(()) => log::info!("wavy_rects: reloaded shader"),
+        Err(e) => log::error!("wavy_rects: shader failed to compile, keeping the old one: {e}"),
     }
 }
 
@@ -42,22 +97,33 @@ impl Widget for &mut WavyRectanglesWithControls {
                     ui.label("edge");
                 });
                 ui.add(
-                    egui::Slider::new(&mut wavy_rectangles.style.line_width_px, 0.0..=10.0)
-                        .text("line width (px)"),
+                    egui::Slider::new(&mut wavy_rectangles.style.line_width, 0.0..=10.0)
+                        .text("line width"),
                 );
                 ui.add(
-                    egui::Slider::new(&mut wavy_rectangles.style.corner_radius_px, 0.0..=50.0)
-                        .text("corner radius (px)"),
+                    egui::Slider::new(&mut wavy_rectangles.style.corner_radius, 0.0..=50.0)
+                        .text("corner radius"),
                 );
+                ui.horizontal(|ui| {
+                    color_map_ui(ui, wavy_rectangles.id, &mut wavy_rectangles.color_map)
+                });
+                egui::CollapsingHeader::new("wave")
+                    .id_source(("wave", wavy_rectangles.id))
+                    .show(ui, |ui| wave_ui(ui, &mut wavy_rectangles.wave));
                 ui.add(
-                    egui::Slider::new(&mut wavy_rectangles.rect_count, 1..=100)
-                        .text("Rectangle count"),
+                    egui::Slider::new(
+                        &mut wavy_rectangles.rect_count,
+                        WavyRectangles::RECT_COUNT_RANGE,
+                    )
+                    .logarithmic(true)
+                    .text("Rectangle count"),
                 );
                 ui.allocate_ui_with_layout(
                     ui.available_size_before_wrap(),
                     Layout::bottom_up(Align::Min),
                     |ui| {
                         ui.add(player::Controller::new(
+                            ("player", wavy_rectangles.id),
                             player,
                             &mut wavy_rectangles.time_seconds,
                         ));
@@ -70,17 +136,171 @@ impl Widget for &mut WavyRectanglesWithControls {
     }
 }
 
+fn wave_ui(ui: &mut egui::Ui, wave: &mut WaveParams) {
+    egui::ComboBox::from_label("easing")
+        .selected_text(wave.easing.name())
+        .show_ui(ui, |ui| {
+            for easing in Easing::ALL {
+                ui.selectable_value(&mut wave.easing, easing, easing.name());
+            }
+        });
+    ui.add(
+        egui::Slider::new(&mut wave.period_seconds, WaveParams::PERIOD_RANGE)
+            .logarithmic(true)
+            .suffix(" s")
+            .text("period"),
+    );
+    ui.add(egui::Slider::new(&mut wave.amplitude, 0.0..=1.0).text("amplitude"));
+    ui.add(egui::Slider::new(&mut wave.phase_spread, 0.0..=5.0).text("wave lengths"));
+    ui.add(
+        egui::Slider::new(&mut wave.rotation_degrees_per_second, -360.0..=360.0)
+            .suffix("°/s")
+            .text("rotation speed"),
+    );
+    ui.checkbox(&mut wave.alternate_rotation, "alternate rotation");
+    ui.add(egui::Slider::new(&mut wave.size_scale, WaveParams::SIZE_SCALE_RANGE).text("size"));
+    ui.add(
+        egui::Slider::new(&mut wave.pulse_amplitude, WaveParams::PULSE_AMPLITUDE_RANGE)
+            .text("pulse"),
+    );
+    ui.add(
+        egui::Slider::new(
+            &mut wave.pulse_period_seconds,
+            WaveParams::PULSE_PERIOD_RANGE,
+        )
+        .logarithmic(true)
+        .suffix(" s")
+        .text("pulse period"),
+    );
+    if ui.button("Reset").clicked() {
+        *wave = WaveParams::default();
+    }
+}
+
+fn color_map_ui(ui: &mut egui::Ui, id: u64, color_map: &mut ColorMap) {
+    egui::ComboBox::from_id_source(("color map", id))
+        .selected_text(color_map.name())
+        .show_ui(ui, |ui| {
+            for option in [
+                ColorMap::Uniform,
+                ColorMap::default_index(),
+                ColorMap::default_time(),
+            ] {
+                if ui
+                    .selectable_label(option.name() == color_map.name(), option.name())
+                    .clicked()
+                    && option.name() != color_map.name()
+                {
+                    *color_map = option;
+                }
+            }
+        });
+    ui.label("color map");
+    match color_map {
+        ColorMap::Uniform => {}
+        ColorMap::Index { start, end } => {
+            ui.color_edit_button_rgba_unmultiplied(start);
+            ui.color_edit_button_rgba_unmultiplied(end);
+        }
+        ColorMap::Time {
+            start,
+            end,
+            period_seconds,
+        } => {
+            ui.color_edit_button_rgba_unmultiplied(start);
+            ui.color_edit_button_rgba_unmultiplied(end);
+            ui.add(
+                egui::DragValue::new(period_seconds)
+                    .clamp_range(ColorMap::PERIOD_RANGE)
+                    .speed(0.1)
+                    .suffix(" s"),
+            );
+        }
+    }
+}
+
+/// Assigns a fill color to each rectangle.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub enum ColorMap {
+    /// Every rect uses the fill from the widget's style.
+    #[default]
+    Uniform,
+    /// Blend from `start` to `end` with the rectangle index.
+    Index { start: [f32; 4], end: [f32; 4] },
+    /// Oscillate between `start` and `end` over time, phase shifted by the
+    /// rectangle index.
+    Time {
+        start: [f32; 4],
+        end: [f32; 4],
+        period_seconds: f32,
+    },
+}
+
+impl ColorMap {
+    /// Allowed values of [`ColorMap::Time`]'s `period_seconds`.
+    pub const PERIOD_RANGE: std::ops::RangeInclusive<f32> = 0.1..=60.0;
+
+    fn default_index() -> Self {
+        Self::Index {
+            start: [0.1, 0.3, 0.9, 1.0],
+            end: [0.9, 0.3, 0.1, 1.0],
+        }
+    }
+
+    fn default_time() -> Self {
+        Self::Time {
+            start: [0.1, 0.3, 0.9, 1.0],
+            end: [0.9, 0.3, 0.1, 1.0],
+            period_seconds: 7.0,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Uniform => "uniform",
+            Self::Index { .. } => "by index",
+            Self::Time { .. } => "by time",
+        }
+    }
+
+    /// Fill color for rect `index` of `count` at `time_seconds`, or `None` to
+    /// use the default fill.
+    pub fn fill(&self, index: u32, count: u32, time_seconds: f32) -> Option<[f32; 4]> {
+        fn mix(a: &[f32; 4], b: &[f32; 4], t: f32) -> [f32; 4] {
+            std::array::from_fn(|i| a[i] + t * (b[i] - a[i]))
+        }
+        let phase = index as f32 / count.max(1) as f32;
+        match self {
+            Self::Uniform => None,
+            Self::Index { start, end } => Some(mix(start, end, phase)),
+            Self::Time {
+                start,
+                end,
+                period_seconds,
+            } => {
+                // Persisted state isn't checked against the UI's range.
+                let (min, max) = Self::PERIOD_RANGE.into_inner();
+                let period_seconds = period_seconds.clamp(min, max);
+                let t = 0.5 - 0.5 * (2.0 * PI * (time_seconds / period_seconds + phase)).cos();
+                Some(mix(start, end, t))
+            }
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
 #[serde(default)]
 pub struct WavyRectangles {
     pub rect_count: u32,
     pub time_seconds: f32,
     pub style: RectPainterSettings,
+    pub color_map: ColorMap,
+    pub wave: WaveParams,
 
     // enables multiple instances of the shader state so we can use the same
     // pipeline for multiple visuals at the same time.
-    #[serde(skip)]
-    id: Option<usize>,
+    #[serde(skip, default = "next_id")]
+    id: u64,
 }
 
 impl Default for WavyRectangles {
@@ -89,35 +309,47 @@ impl Default for WavyRectangles {
             rect_count: 20,
             time_seconds: 0.0,
             style: Default::default(),
-            id: None,
+            color_map: Default::default(),
+            wave: Default::default(),
+            id: next_id(),
         }
     }
 }
 
 impl WavyRectangles {
-    pub fn setup_renderer<'a>(&mut self, cc: &'a CreationContext<'a>) {
-        let rc = cc.wgpu_render_state.clone().unwrap();
-        let painter = RectPainter::new(&rc);
-
-        // Because the graphics pipeline must have the same lifetime as the egui render pass,
-        // instead of storing the pipeline in our `MyShader` struct, we insert it into the
-        // `paint_callback_resources` type map, which is stored alongside the render pass.
-        // rc.renderer.write().callback_resources.insert(painter);
-
-        // I think the idea with the callback resources is that those resources
-        // are stateless somehow, so that they can be shared between widgets.
-        // e.g. the pipeline is invariant. The particular bindings vary though.
-        // Maybe their lifetime is different?
-        // Anyway, I think I've done it wrong here. Should see if I can
-        // separate the bindings.
-
-        let mut renderer = rc.renderer.write();
-        let e = renderer
-            .callback_resources
-            .entry::<Vec<RectPainter>>()
-            .or_insert(Vec::new());
-        self.id = Some(e.len());
-        e.push(painter);
+    /// Allowed values of `rect_count`.
+    pub const RECT_COUNT_RANGE: std::ops::RangeInclusive<u32> = 1..=100_000;
+
+    /// The rects to draw in an area `size` points in size, in points
+    /// relative to its top left.
+    pub(super) fn rects(&self, size: Vec2) -> Vec<Rect> {
+        // Persisted state isn't checked against the UI's range.
+        let (min, max) = Self::RECT_COUNT_RANGE.into_inner();
+        let rect_count = self.rect_count.clamp(min, max);
+        let mut rects = make_rects(
+            self.time_seconds,
+            &self.wave,
+            rect_count,
+            0.0,
+            size.x,
+            0.0,
+            size.y,
+        );
+        for (i, rect) in rects.iter_mut().enumerate() {
+            if let Some(fill) = self.color_map.fill(i as _, rect_count, self.time_seconds) {
+                rect.style.fill = fill;
+            }
+        }
+        rects
+    }
+
+    /// Frees the GPU resources used by this widget.
+    ///
+    /// They are allocated on demand when the widget is painted.
+    pub fn release(&self, resources: &mut CallbackResources) {
+        if let Some(painters) = resources.get_mut::<RectPainters>() {
+            painters.remove(self.id);
+        }
     }
 }
 
@@ -130,14 +362,33 @@ impl Widget for WavyRectangles {
             egui::Sense::focusable_noninteractive(),
         );
 
-        ui.painter()
-            .add(egui_wgpu::Callback::new_paint_callback(rect, self));
+        if gpu::is_available(ui.ctx()) {
+            // Matches the `PaintCallbackInfo::viewport` the callback gets,
+            // which `prepare` doesn't see.
+            let viewport = Viewport::from_points(rect.size(), ui.ctx().pixels_per_point());
+            ui.painter().add(egui_wgpu::Callback::new_paint_callback(
+                rect,
+                WavyRectanglesCallback {
+                    rects: self,
+                    viewport,
+                },
+            ));
+        } else {
+            let painter = ui.painter_at(rect);
+            fallback::paint(&painter, rect, &self.rects(rect.size()), &self.style);
+            gpu::paint_unavailable_notice(&painter, rect);
+        }
 
         response
     }
 }
 
-impl egui_wgpu::CallbackTrait for WavyRectangles {
+struct WavyRectanglesCallback {
+    rects: WavyRectangles,
+    viewport: Viewport,
+}
+
+impl egui_wgpu::CallbackTrait for WavyRectanglesCallback {
     fn paint<'a>(
         &'a self,
         _info: egui::PaintCallbackInfo,
@@ -145,39 +396,34 @@ impl egui_wgpu::CallbackTrait for WavyRectangles {
         callback_resources: &'a egui_wgpu::CallbackResources,
     ) {
         puffin::profile_function!();
-        if let Some(id) = self.id {
-            let painter: &RectPainter =
-                callback_resources.get::<Vec<_>>().unwrap().get(id).unwrap();
-            painter.paint(render_pass);
+        if let Some(painters) = callback_resources.get::<RectPainters>() {
+            painters.paint(self.rects.id, render_pass);
         }
     }
 
     fn prepare(
         &self,
-        _device: &eframe::wgpu::Device,
+        device: &eframe::wgpu::Device,
         queue: &eframe::wgpu::Queue,
         _egui_encoder: &mut eframe::wgpu::CommandEncoder,
         callback_resources: &mut egui_wgpu::CallbackResources,
     ) -> Vec<eframe::wgpu::CommandBuffer> {
         puffin::profile_function!();
-        if let Some(id) = self.id {
-            let painter: &mut RectPainter = callback_resources
-                .get_mut::<Vec<_>>()
-                .unwrap()
-                .get_mut(id)
-                .unwrap();
-            let (vertices, indices) = encode_geometry(&make_rects(
-                self.time_seconds,
-                5.0,
-                self.rect_count,
-                -0.9,
-                0.9,
-                -0.9,
-                0.9,
-            ));
-            painter.set_geometry(queue, &vertices, &indices);
-            painter.set_uniforms(queue, &self.style);
-        }
+        let Some(target_format) = gpu::target_format(callback_resources) else {
+            return Vec::new();
+        };
+        let painter = callback_resources
+            .entry::<RectPainters>()
+            .or_insert_with(|| RectPainters::new(device, target_format))
+            .get_or_insert(device, self.rects.id);
+        let rects = self.rects.rects(self.viewport.size());
+        painter.set_rects(device, queue, &rects);
+        trace!(
+            "rect painter {} capacity: {} rects",
+            self.rects.id,
+            painter.capacity()
+        );
+        painter.set_uniforms(queue, &self.rects.style, self.viewport);
         Vec::new()
     }
 
@@ -192,95 +438,124 @@ impl egui_wgpu::CallbackTrait for WavyRectangles {
     }
 }
 
-struct Rect {
-    center: [f32; 2],
-    size: [f32; 2],
-    orientation_radians: f32,
-}
+#[cfg(test)]
+mod tests {
+    use eframe::{
+        egui_wgpu::{renderer::ScreenDescriptor, Renderer},
+        wgpu,
+    };
+    use egui::{epaint::Shape, pos2, Event, Modifiers, PointerButton, Pos2, RawInput};
+
+    use super::*;
+    use crate::widgets::gpu::TargetFormat;
+
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+    const SCREEN_SIZE: [u32; 2] = [800, 600];
 
-// x0,x1,y0,y1 are the bounds within which the rectangles should be generated.
-// They should be in clip space.
-fn make_rects(
-    time_seconds: f32,
-    scale: f32,
-    steps: u32,
-    x0: f32,
-    x1: f32,
-    y0: f32,
-    y1: f32,
-) -> Vec<Rect> {
-    puffin::profile_function!();
-    let dx = (x1 - x0) / (steps + 1) as f32;
-    let dy = y1 - y0;
-    let sz = dx.max(0.1);
-    (0..steps)
-        .map(|i| {
-            let is_odd = (i & 1) == 1;
-            let i = i as f32;
-            let ph = 2.0 * PI * i / (steps + 1) as f32;
-            let cx = x0 + dx * (i + 0.5);
-            let cy = y0 + 0.5 * dy * (1.0 + (ph + 2.0 * PI * time_seconds / 7.0).cos());
-            let w = scale * sz; // + 1.0 * sz * (2.0 * PI * time_seconds / 0.5).cos();
-            let h = scale * sz; // + 1.0 * sz * (2.0 * PI * (0.3 + time_seconds / 3.0)).cos();
-            let th = (2.0 * PI * time_seconds / 7.0) * if is_odd { 1.0 } else { -1.0 };
-            Rect {
-                center: [cx, cy],
-                size: [w, h],
-                orientation_radians: th,
+    struct App {
+        ctx: egui::Context,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        renderer: Renderer,
+        widgets: Vec<WavyRectanglesWithControls>,
+    }
+
+    impl App {
+        /// Runs a frame like `MainApp` does, and then the paint callbacks'
+        /// `prepare` like eframe does. Returns the shapes that were painted.
+        fn frame(&mut self, events: Vec<Event>) -> Vec<Shape> {
+            let [width, height] = SCREEN_SIZE;
+            let input = RawInput {
+                screen_rect: Some(egui::Rect::from_min_max(
+                    pos2(0.0, 0.0),
+                    pos2(width as _, height as _),
+                )),
+                events,
+                ..Default::default()
+            };
+            let widgets = &mut self.widgets;
+            let output = self.ctx.run(input, |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    if let Some(widget) = show_panels(ui, widgets) {
+                        widget.release(&mut self.renderer.callback_resources);
+                    }
+                });
+            });
+            let shapes = output.shapes.iter().map(|s| s.shape.clone()).collect();
+            let primitives = self.ctx.tessellate(output.shapes);
+            let mut encoder = self.device.create_command_encoder(&Default::default());
+            self.renderer.update_buffers(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &primitives,
+                &ScreenDescriptor {
+                    size_in_pixels: SCREEN_SIZE,
+                    pixels_per_point: 1.0,
+                },
+            );
+            self.queue.submit([encoder.finish()]);
+            shapes
+        }
+
+        fn painters(&self) -> &RectPainters {
+            self.renderer.callback_resources.get().unwrap()
+        }
+    }
+
+    fn find_text(shapes: &[Shape], text: &str) -> Option<Pos2> {
+        shapes.iter().find_map(|shape| match shape {
+            Shape::Vec(shapes) => find_text(shapes, text),
+            Shape::Text(t) if t.galley.text() == text => {
+                Some(egui::Rect::from_min_size(t.pos, t.galley.size()).center())
             }
+            _ => None,
         })
-        .collect()
-}
+    }
 
-fn encode_geometry(rects: &[Rect]) -> (Vec<Vertex>, Vec<u32>) {
-    puffin::profile_function!();
-    fn mk_vertices(rect: &Rect) -> [Vertex; 3] {
-        let [cx, cy] = rect.center;
-        let [half_w, half_h] = rect.size.map(|e| 0.5 * e);
-        let side = half_h + half_w;
-        let (s, c) = rect.orientation_radians.sin_cos();
-
-        // The rectangle lies on two sides of the triangle
-        // FIXME: need a 1 px padding on those sides for anti-aliasing.
-
-        // create an isosceles right triangle within which the rect will be painted
-        // center is at uv: [0,0]
-        // rect's [w,h] in uv coords is [1,1]
-        [
-            // top-left
-            Vertex {
-                xyz: [-half_w, -half_h, 0.0],
-                uv: [-0.5, -0.5],
-            },
-            // bottom-right
-            Vertex {
-                xyz: [2.0 * half_h - half_w, -half_h, 0.0],
-                uv: [-0.5 + side / half_h, -0.5],
-            },
-            // bottom-left
-            Vertex {
-                xyz: [-half_w, 2.0 * half_w - half_h, 0.0],
-                uv: [-0.5, -0.5 + side / half_w],
-            },
-        ]
-        .map(|mut v| {
-            // rotate about (0,0) by theta
-            // then translate
-            v.xyz[0] += half_w * 0.5;
-            v.xyz[1] += half_h * 0.5;
-            let x = v.xyz[0] * c - v.xyz[1] * s;
-            let y = v.xyz[0] * s + v.xyz[1] * c;
-            v.xyz[0] = x + cx;
-            v.xyz[1] = y + cy;
-            v
-        })
+    #[test]
+    fn rect_count_is_clamped() {
+        let size = vec2(100.0, 100.0);
+        let rects = |rect_count| {
+            WavyRectangles {
+                rect_count,
+                ..Default::default()
+            }
+            .rects(size)
+        };
+        assert_eq!(rects(0).len(), 1);
+        assert_eq!(rects(20).len(), 20);
+        assert_eq!(rects(u32::MAX).len(), 100_000);
     }
 
-    let verts = rects
-        .into_iter()
-        .map(|r| mk_vertices(r))
-        .flatten()
-        .collect();
-    let idxs = (0..3 * rects.len() as u32).collect();
-    (verts, idxs)
+    #[test]
+    fn removed_widgets_stay_released() {
+        let Some((device, queue)) = gpu::test_device() else {
+            return;
+        };
+        let mut renderer = Renderer::new(&device, FORMAT, None, 1);
+        renderer.callback_resources.insert(TargetFormat(FORMAT));
+        let mut app = App {
+            ctx: Default::default(),
+            device,
+            queue,
+            renderer,
+            widgets: vec![Default::default()],
+        };
+
+        let shapes = app.frame(Vec::new());
+        assert!(!app.painters().is_empty());
+
+        let remove = find_text(&shapes, "✖").unwrap();
+        let click = |pressed| Event::PointerButton {
+            pos: remove,
+            button: PointerButton::Primary,
+            pressed,
+            modifiers: Modifiers::default(),
+        };
+        app.frame(vec![Event::PointerMoved(remove), click(true)]);
+        app.frame(vec![click(false)]);
+        assert!(app.widgets.is_empty());
+        assert!(app.painters().is_empty());
+    }
 }
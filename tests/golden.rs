@@ -0,0 +1,216 @@
+//! Golden image tests for the widgets' shaders.
+//!
+//! Each test renders with fixed settings on a headless device (preferably a
+//! software adapter) and compares the result to a reference PNG in
+//! `tests/golden/`. On a mismatch the actual image and a diff are written
+//! next to the test binary's temporary directory.
+//!
+//! To accept new output, run with `UPDATE_GOLDEN=1`. Tests that need a wgpu
+//! adapter fail without one, unless run with `SKIP_GPU_TESTS=1`.
+
+use std::path::{Path, PathBuf};
+
+use egui::{Color32, ColorImage};
+use try_egui_eframe::widgets::{
+    export::ExportError,
+    gpu, simple_image,
+    wavy_rects::{
+        self,
+        ui::{ColorMap, WavyRectangles},
+    },
+};
+
+// Rasterizers differ a little in how they round, so allow some slack per
+// color channel.
+const TOLERANCE: u8 = 3;
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn load(path: &Path) -> Option<ColorImage> {
+    let image = image::open(path).ok()?.to_rgba8();
+    let size = [image.width() as usize, image.height() as usize];
+    Some(ColorImage::from_rgba_unmultiplied(size, image.as_raw()))
+}
+
+fn save(image: &ColorImage, path: &Path) {
+    let [width, height] = image.size;
+    let rgba = image
+        .pixels
+        .iter()
+        .flat_map(|p| p.to_srgba_unmultiplied())
+        .collect();
+    image::RgbaImage::from_raw(width as u32, height as u32, rgba)
+        .unwrap()
+        .save(path)
+        .unwrap();
+}
+
+fn close(a: Color32, b: Color32) -> bool {
+    a.to_array()
+        .iter()
+        .zip(b.to_array())
+        .all(|(&a, b)| a.abs_diff(b) <= TOLERANCE)
+}
+
+/// Mismatched pixels in red over a faded copy of the reference.
+fn diff(expected: &ColorImage, actual: &ColorImage) -> (ColorImage, usize) {
+    let mut mismatches = 0;
+    let pixels = expected
+        .pixels
+        .iter()
+        .zip(&actual.pixels)
+        .map(|(&e, &a)| {
+            if close(e, a) {
+                let [r, g, b, _] = e.to_array();
+                let gray = ((r as u32 + g as u32 + b as u32) / 3) as u8;
+                Color32::from_gray(gray / 4)
+            } else {
+                mismatches += 1;
+                Color32::RED
+            }
+        })
+        .collect();
+    let image = ColorImage {
+        size: expected.size,
+        pixels,
+    };
+    (image, mismatches)
+}
+
+fn check(name: &str, actual: &ColorImage) {
+    check_against(name, name, actual, 0);
+}
+
+/// Compares `actual` to the reference image for `reference`, allowing up to
+/// `allowed_mismatches` pixels to differ. References are only updated when
+/// `name == reference`.
+fn check_against(name: &str, reference: &str, actual: &ColorImage, allowed_mismatches: usize) {
+    let updating = name == reference;
+    let reference = golden_dir().join(format!("{reference}.png"));
+    if updating && std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        save(actual, &reference);
+        eprintln!("updated {}", reference.display());
+        return;
+    }
+    let expected = load(&reference).unwrap_or_else(|| {
+        panic!(
+            "missing {}; run with UPDATE_GOLDEN=1 to create it",
+            reference.display()
+        )
+    });
+
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&out).unwrap();
+    let actual_path = out.join(format!("{name}.actual.png"));
+    if expected.size != actual.size {
+        save(actual, &actual_path);
+        panic!(
+            "{name}: size {:?} differs from the reference's {:?}\nactual: {}",
+            actual.size,
+            expected.size,
+            actual_path.display(),
+        );
+    }
+    let (diff, mismatches) = diff(&expected, actual);
+    if mismatches > allowed_mismatches {
+        let diff_path = out.join(format!("{name}.diff.png"));
+        save(actual, &actual_path);
+        save(&diff, &diff_path);
+        panic!(
+            "{name}: {mismatches} pixels differ from {} by more than {TOLERANCE} \
+             ({allowed_mismatches} allowed)\n\
+             actual: {}\n\
+             diff: {}",
+            reference.display(),
+            actual_path.display(),
+            diff_path.display(),
+        );
+    }
+}
+
+#[test]
+fn wavy_rects_default() {
+    let Some((device, queue)) = gpu::test_device() else {
+        return;
+    };
+    let mut rects = WavyRectangles::default();
+    rects.time_seconds = 1.5;
+    let image = wavy_rects::export::render(&device, &queue, &rects, 256, 256).unwrap();
+    check("wavy_rects_default", &image);
+}
+
+fn styled_rects() -> WavyRectangles {
+    let mut rects = WavyRectangles::default();
+    rects.rect_count = 50;
+    rects.time_seconds = 4.0;
+    rects.style.line_width = 3.0;
+    rects.style.corner_radius = 6.0;
+    rects.color_map = ColorMap::Index {
+        start: [0.1, 0.3, 0.9, 1.0],
+        end: [0.9, 0.3, 0.1, 0.5],
+    };
+    rects
+}
+
+#[test]
+fn wavy_rects_styled() {
+    let Some((device, queue)) = gpu::test_device() else {
+        return;
+    };
+    let image = wavy_rects::export::render(&device, &queue, &styled_rects(), 320, 200).unwrap();
+    check("wavy_rects_styled", &image);
+}
+
+// The CPU rasterizer should agree with the GPU, except maybe for a few
+// pixels on triangle edges, where the rasterization rules differ.
+#[test]
+fn wavy_rects_cpu_matches_gpu() {
+    let mut rects = WavyRectangles::default();
+    rects.time_seconds = 1.5;
+    let image = wavy_rects::export::render_cpu(&rects, 256, 256);
+    check_against("wavy_rects_default_cpu", "wavy_rects_default", &image, 8);
+
+    let image = wavy_rects::export::render_cpu(&styled_rects(), 320, 200);
+    check_against("wavy_rects_styled_cpu", "wavy_rects_styled", &image, 8);
+}
+
+#[test]
+fn simple_image_start() {
+    let Some((device, queue)) = gpu::test_device() else {
+        return;
+    };
+    let settings = simple_image::Settings { time: 0.0 };
+    let image = simple_image::export::render(&device, &queue, &settings, 128, 96).unwrap();
+    check("simple_image_start", &image);
+}
+
+#[test]
+fn simple_image_partial_workgroups() {
+    let Some((device, queue)) = gpu::test_device() else {
+        return;
+    };
+    // Neither side is a multiple of the workgroup size, and the rows need
+    // padding for the readback.
+    let settings = simple_image::Settings { time: 2.0 };
+    let image = simple_image::export::render(&device, &queue, &settings, 131, 67).unwrap();
+    check("simple_image_partial_workgroups", &image);
+}
+
+#[test]
+fn exports_reject_bad_sizes() {
+    let Some((device, queue)) = gpu::test_device() else {
+        return;
+    };
+    let too_big = device.limits().max_texture_dimension_2d + 1;
+    for [width, height] in [[0, 64], [64, 0], [too_big, 64]] {
+        let result =
+            wavy_rects::export::render(&device, &queue, &WavyRectangles::default(), width, height);
+        assert!(matches!(result, Err(ExportError::InvalidSize { .. })));
+        let settings = simple_image::Settings { time: 0.0 };
+        let result = simple_image::export::render(&device, &queue, &settings, width, height);
+        assert!(matches!(result, Err(ExportError::InvalidSize { .. })));
+    }
+}
@@ -0,0 +1,101 @@
+//! Tests for the widgets as an app shows them, run through egui without a
+//! window.
+
+use egui::{epaint::Shape, pos2, Context, RawInput, Rect};
+use try_egui_eframe::widgets::{
+    gpu, simple_image::ui::SimpleImage, wavy_rects, wavy_rects::ui::WavyRectanglesWithControls,
+};
+
+fn input() -> RawInput {
+    RawInput {
+        screen_rect: Some(Rect::from_min_max(pos2(0.0, 0.0), pos2(800.0, 600.0))),
+        ..Default::default()
+    }
+}
+
+/// Runs a frame and returns what it painted, with nested shapes flattened.
+fn run(ctx: &Context, mut add_contents: impl FnMut(&mut egui::Ui)) -> Vec<Shape> {
+    fn flatten(shape: Shape, out: &mut Vec<Shape>) {
+        match shape {
+            Shape::Vec(shapes) => shapes.into_iter().for_each(|s| flatten(s, out)),
+            shape => out.push(shape),
+        }
+    }
+    let output = ctx.run(input(), |ctx| {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| add_contents(ui));
+        });
+    });
+    let mut shapes = Vec::new();
+    for clipped in output.shapes {
+        flatten(clipped.shape, &mut shapes);
+    }
+    shapes
+}
+
+fn count_callbacks(shapes: &[Shape]) -> usize {
+    shapes
+        .iter()
+        .filter(|s| matches!(s, Shape::Callback(_)))
+        .count()
+}
+
+fn count_text(shapes: &[Shape], text: &str) -> usize {
+    shapes
+        .iter()
+        .filter(|s| matches!(s, Shape::Text(t) if t.galley.text() == text))
+        .count()
+}
+
+#[test]
+fn widgets_paint_with_wgpu_by_default() {
+    let ctx = Context::default();
+    let mut rects = WavyRectanglesWithControls::default();
+    let mut image = SimpleImage::default();
+    let shapes = run(&ctx, |ui| {
+        ui.add(&mut rects);
+        ui.add(&mut image);
+    });
+    assert_eq!(count_callbacks(&shapes), 2);
+    assert_eq!(count_text(&shapes, "GPU unavailable"), 0);
+}
+
+#[test]
+fn widgets_fall_back_to_the_cpu_without_wgpu() {
+    let ctx = Context::default();
+    gpu::install(&ctx, None);
+    let mut rects = WavyRectanglesWithControls::default();
+    let mut image = SimpleImage::default();
+    let shapes = run(&ctx, |ui| {
+        ui.add(&mut rects);
+        ui.add(&mut image);
+    });
+    assert_eq!(count_callbacks(&shapes), 0);
+    assert_eq!(count_text(&shapes, "GPU unavailable"), 2);
+    // the simple image's fallback is drawn as a texture
+    assert!(shapes
+        .iter()
+        .any(|s| matches!(s, Shape::Mesh(m) if m.texture_id != egui::TextureId::default())));
+}
+
+// Each panel has a player, whose loop mode and clock combo boxes used to
+// share ids with the other players'.
+#[test]
+fn panels_have_their_own_ids() {
+    let ctx = Context::default();
+    let mut widgets: Vec<WavyRectanglesWithControls> = (0..2).map(|_| Default::default()).collect();
+    let mut image = SimpleImage::default();
+    let shapes = run(&ctx, |ui| {
+        wavy_rects::ui::show_panels(ui, &mut widgets);
+        ui.add(&mut image);
+    });
+    // egui paints a warning for each clash
+    let clashes: Vec<_> = shapes
+        .iter()
+        .filter_map(|s| match s {
+            Shape::Text(t) if t.galley.text().starts_with('🔥') => Some(t.galley.text()),
+            _ => None,
+        })
+        .collect();
+    assert!(clashes.is_empty(), "{clashes:?}");
+}